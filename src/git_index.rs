@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+/// Mode git records for a submodule.
+pub const GITLINK_MODE: u32 = 0o160000;
+
+pub struct Entry {
+    /// Path relative to the top of the working tree.
+    pub path: PathBuf,
+    pub mtime_secs: u32,
+    pub mtime_nsecs: u32,
+    pub mode: u32,
+    /// File size truncated to 32 bits, as git stores it.
+    pub size: u32,
+    /// Merge stage; anything but 0 is an unresolved conflict.
+    pub stage: u16,
+}
+
+pub struct Index {
+    pub entries: Vec<Entry>,
+    /// The tree the whole index would be written as, when git's cache of
+    /// it is up to date.  Staging anything invalidates it.
+    pub tree: Option<[u8; 20]>,
+}
+
+/// Reads the index (format versions 2 to 4) of the repository whose git
+/// directory is `git_dir`.  Returns `None` for anything not understood,
+/// such as a SHA-256 repository, a split index or sparse directory entries,
+/// so callers can fall back to asking git.
+pub fn read(git_dir: &Path) -> Option<Index> {
+    let config = std::fs::read_to_string(git_dir.join("config")).unwrap_or_default();
+    if config.to_ascii_lowercase().contains("objectformat") {
+        return None;
+    }
+    let data = std::fs::read(git_dir.join("index")).ok()?;
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(&data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be32(&data, 8)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 12;
+    let mut prev_name: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = pos;
+        let mtime_secs = be32(&data, pos + 8)?;
+        let mtime_nsecs = be32(&data, pos + 12)?;
+        let mode = be32(&data, pos + 24)?;
+        let size = be32(&data, pos + 36)?;
+        // 40 bytes of stat data, then the 20 byte object id.
+        pos += 60;
+        let flags = be16(&data, pos)?;
+        pos += 2;
+        if version >= 3 && flags & 0x4000 != 0 {
+            pos += 2;
+        }
+        let name = if version == 4 {
+            let (strip, used) = varint(data.get(pos..)?)?;
+            pos += used;
+            let keep = prev_name.len().checked_sub(strip)?;
+            let end = pos + data.get(pos..)?.iter().position(|&b| b == 0)?;
+            let mut name = prev_name[..keep].to_vec();
+            name.extend_from_slice(&data[pos..end]);
+            pos = end + 1;
+            name
+        } else {
+            let end = pos + data.get(pos..)?.iter().position(|&b| b == 0)?;
+            let name = data[pos..end].to_vec();
+            // Entries are NUL-padded to a multiple of eight bytes.
+            pos = start + (end - start + 8) / 8 * 8;
+            name
+        };
+        entries.push(Entry {
+            path: path_from_bytes(&name),
+            mtime_secs,
+            mtime_nsecs,
+            mode,
+            size,
+            stage: (flags >> 12) & 0x3,
+        });
+        prev_name = name;
+    }
+    // Extensions follow the entries; some of them change what the entries
+    // mean, so bail out on those.  The last 20 bytes are the checksum.
+    let mut tree = None;
+    while pos + 8 <= data.len().saturating_sub(20) {
+        let signature = &data[pos..pos + 4];
+        if signature == b"link" || signature == b"sdir" {
+            return None;
+        }
+        let len = be32(&data, pos + 4)? as usize;
+        if signature == b"TREE" {
+            tree = root_tree(data.get(pos + 8..pos + 8 + len)?);
+        }
+        pos += 8 + len;
+    }
+    Some(Index { entries, tree })
+}
+
+/// The root entry of the cache-tree extension: an empty path, then the
+/// entry count (-1 once invalidated), the subtree count and the tree id.
+fn root_tree(ext: &[u8]) -> Option<[u8; 20]> {
+    let rest = ext.strip_prefix(b"\0")?;
+    let newline = rest.iter().position(|&b| b == b'\n')?;
+    let counts = std::str::from_utf8(&rest[..newline]).ok()?;
+    let entry_count: i64 = counts.split(' ').next()?.parse().ok()?;
+    if entry_count < 0 {
+        return None;
+    }
+    rest.get(newline + 1..newline + 21)?.try_into().ok()
+}
+
+fn be32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn be16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+/// git's offset encoding for the prefix length in version 4 entries.
+fn varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut used = 0;
+    let mut byte = *data.first()?;
+    let mut value = usize::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        used += 1;
+        byte = *data.get(used)?;
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+    }
+    Some((value, used + 1))
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Object id of the tree the commit at HEAD records, for the repository
+/// whose git directory is `git_dir`.  `None` for an unborn branch and for
+/// anything not understood, such as a commit stored as a delta in a pack.
+pub fn head_tree(git_dir: &Path) -> Option<[u8; 20]> {
+    let common = common_dir(git_dir);
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let commit = match head.trim().strip_prefix("ref: ") {
+        Some(name) => resolve_ref(git_dir, &common, name)?,
+        None => parse_hex(head.trim())?,
+    };
+    let data = read_object(&common, &commit, 1)?;
+    let hex = data.strip_prefix(b"tree ")?.get(..40)?;
+    parse_hex(std::str::from_utf8(hex).ok()?)
+}
+
+/// Where refs and objects live: the main git directory for a linked
+/// worktree, otherwise `git_dir` itself.
+fn common_dir(git_dir: &Path) -> PathBuf {
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(dir) => git_dir.join(dir.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+fn resolve_ref(git_dir: &Path, common: &Path, name: &str) -> Option<[u8; 20]> {
+    for dir in [git_dir, common] {
+        if let Ok(value) = std::fs::read_to_string(dir.join(name)) {
+            return match value.trim().strip_prefix("ref: ") {
+                Some(next) => resolve_ref(git_dir, common, next),
+                None => parse_hex(value.trim()),
+            };
+        }
+    }
+    let packed = std::fs::read_to_string(common.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| match line.split_once(' ') {
+        Some((hex, refname)) if refname == name => parse_hex(hex),
+        _ => None,
+    })
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut oid = [0; 20];
+    for (i, byte) in oid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(oid)
+}
+
+/// The contents of object `oid`, which must be of pack type `kind` (1 for
+/// a commit), looked up loose first and then in each pack.
+fn read_object(common: &Path, oid: &[u8; 20], kind: u8) -> Option<Vec<u8>> {
+    let hex: String = oid.iter().map(|b| format!("{:02x}", b)).collect();
+    let objects = common.join("objects");
+    if let Ok(compressed) = std::fs::read(objects.join(&hex[..2]).join(&hex[2..])) {
+        let data = inflate(&compressed)?;
+        let nul = data.iter().position(|&b| b == 0)?;
+        let header = std::str::from_utf8(&data[..nul]).ok()?;
+        let type_name = match kind {
+            1 => "commit ",
+            _ => return None,
+        };
+        header.strip_prefix(type_name)?;
+        return Some(data[nul + 1..].to_vec());
+    }
+    for entry in std::fs::read_dir(objects.join("pack")).ok()?.flatten() {
+        let idx = entry.path();
+        if idx.extension().is_none_or(|ext| ext != "idx") {
+            continue;
+        }
+        if let Some(offset) = pack_offset(&idx, oid) {
+            return read_packed(&idx.with_extension("pack"), offset, kind);
+        }
+    }
+    None
+}
+
+/// Offset of `oid` in the pack that the version 2 index `idx` describes.
+fn pack_offset(idx: &Path, oid: &[u8; 20]) -> Option<u64> {
+    let mut file = File::open(idx).ok()?;
+    let mut header = [0; 8 + 256 * 4];
+    file.read_exact(&mut header).ok()?;
+    if header[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+        return None;
+    }
+    let fanout = |i: usize| u32::from_be_bytes(header[8 + i * 4..12 + i * 4].try_into().unwrap()) as u64;
+    let total = fanout(255);
+    let first = oid[0] as usize;
+    let start = if first == 0 { 0 } else { fanout(first - 1) };
+    let end = fanout(first);
+    // Only the ids sharing the first byte need reading.
+    let mut ids = vec![0; ((end - start) * 20) as usize];
+    file.seek(SeekFrom::Start(header.len() as u64 + start * 20)).ok()?;
+    file.read_exact(&mut ids).ok()?;
+    let pos = start + ids.chunks(20).position(|id| id == oid)? as u64;
+
+    let offsets = header.len() as u64 + total * 24;
+    let mut word = [0; 4];
+    file.seek(SeekFrom::Start(offsets + pos * 4)).ok()?;
+    file.read_exact(&mut word).ok()?;
+    let offset = u32::from_be_bytes(word);
+    if offset & 0x8000_0000 == 0 {
+        return Some(offset as u64);
+    }
+    let mut large = [0; 8];
+    file.seek(SeekFrom::Start(offsets + total * 4 + (offset & 0x7fff_ffff) as u64 * 8)).ok()?;
+    file.read_exact(&mut large).ok()?;
+    Some(u64::from_be_bytes(large))
+}
+
+/// An object stored whole at `offset` in `pack`; deltas are not followed.
+fn read_packed(pack: &Path, offset: u64, kind: u8) -> Option<Vec<u8>> {
+    let mut file = File::open(pack).ok()?;
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut byte = [0; 1];
+    file.read_exact(&mut byte).ok()?;
+    if (byte[0] >> 4) & 0x7 != kind {
+        return None;
+    }
+    let mut size = (byte[0] & 0x0f) as usize;
+    let mut shift = 4;
+    while byte[0] & 0x80 != 0 {
+        file.read_exact(&mut byte).ok()?;
+        size |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+    // Compressing never grows data by more than a few bytes per block.
+    let mut compressed = Vec::new();
+    file.take(size as u64 + 256).read_to_end(&mut compressed).ok()?;
+    let data = inflate(&compressed)?;
+    (data.len() == size).then_some(data)
+}
+
+/// Reads a deflate stream a few bits at a time, least significant first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u64,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            self.buf |= (*self.data.get(self.pos)? as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = (self.buf & ((1 << n) - 1)) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Some(value)
+    }
+}
+
+/// A canonical Huffman code as the symbol count per code length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompresses a zlib stream, as git stores its objects.
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    // CM must be deflate and no preset dictionary may be asked for.
+    if data.len() < 2 || data[0] & 0x0f != 8 || data[1] & 0x20 != 0 {
+        return None;
+    }
+    let mut bits = Bits { data: &data[2..], pos: 0, buf: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.buf = 0;
+                bits.count = 0;
+                let header = bits.data.get(bits.pos..bits.pos + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                bits.pos += 4;
+                out.extend_from_slice(bits.data.get(bits.pos..bits.pos + len)?);
+                bits.pos += len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    let nlit = bits.take(5)? as usize + 257;
+    let ndist = bits.take(5)? as usize + 1;
+    let ncode = bits.take(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..ncode] {
+        code_lengths[i] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+        let (value, repeat) = match code.decode(bits)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => (*lengths.last()?, 3 + bits.take(2)?),
+            17 => (0, 3 + bits.take(3)?),
+            18 => (0, 11 + bits.take(7)?),
+            _ => return None,
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != nlit + ndist {
+        return None;
+    }
+    Some((Huffman::new(&lengths[..nlit]), Huffman::new(&lengths[nlit..])))
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Option<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Some(());
+        }
+        let i = symbol - 257;
+        let len = *LENGTH_BASE.get(i)? as usize + bits.take(*LENGTH_EXTRA.get(i)? as u32)? as usize;
+        let d = dist.decode(bits)? as usize;
+        let back = *DIST_BASE.get(d)? as usize + bits.take(*DIST_EXTRA.get(d)? as u32)? as usize;
+        let start = out.len().checked_sub(back)?;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
@@ -1,12 +1,17 @@
+mod git_index;
+mod git_object;
+mod mime;
 mod symbols;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{self, DirEntry, FileType, Metadata};
-use std::io::{self, Write};
+use std::ffi::OsString;
+use std::fs::{self, FileType, Metadata};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Clone, Copy)]
 struct ColorScheme {
@@ -20,12 +25,34 @@ struct ColorScheme {
     block_device: &'static str,
     char_device: &'static str,
     broken_symlink: &'static str,
+    broken_target: &'static str,
     git_new: &'static str,
     git_modified: &'static str,
     git_deleted: &'static str,
     git_renamed: &'static str,
     git_untracked: &'static str,
     git_ignored: &'static str,
+    git_conflicted: &'static str,
+    /// `--git-columns` letters for the index and for the working tree.
+    git_staged: &'static str,
+    git_unstaged: &'static str,
+    rename_source: &'static str,
+    highlight: &'static str,
+    blame: &'static str,
+    new_badge: &'static str,
+    links: &'static str,
+    inode: &'static str,
+    size_small: &'static str,
+    size_medium: &'static str,
+    size_large: &'static str,
+    size_huge: &'static str,
+    mime_text: &'static str,
+    mime_image: &'static str,
+    mime_audio: &'static str,
+    mime_video: &'static str,
+    mime_application: &'static str,
+    /// Repeating palette for `--depth-colors`, one entry per tree level.
+    depth: [&'static str; 6],
 }
 
 impl ColorScheme {
@@ -41,12 +68,32 @@ impl ColorScheme {
             block_device: "\x1b[34m",
             char_device: "\x1b[33m",
             broken_symlink: "\x1b[31m",
+            broken_target: "\x1b[2;9;31m",
             git_new: "\x1b[32m",
             git_modified: "\x1b[34m",
             git_deleted: "\x1b[31m",
             git_renamed: "\x1b[33m",
             git_untracked: "\x1b[90m",
             git_ignored: "\x1b[90m",
+            git_conflicted: "\x1b[1;31m",
+            git_staged: "\x1b[32m",
+            git_unstaged: "\x1b[31m",
+            rename_source: "\x1b[2m",
+            highlight: "\x1b[1;7m",
+            blame: "\x1b[2m",
+            new_badge: "\x1b[1;32m",
+            links: "\x1b[2m",
+            inode: "\x1b[35m",
+            size_small: "\x1b[32m",
+            size_medium: "\x1b[33m",
+            size_large: "\x1b[31m",
+            size_huge: "\x1b[1;31m",
+            mime_text: "\x1b[37m",
+            mime_image: "\x1b[35m",
+            mime_audio: "\x1b[36m",
+            mime_video: "\x1b[95m",
+            mime_application: "\x1b[93m",
+            depth: ["\x1b[34m", "\x1b[32m", "\x1b[33m", "\x1b[35m", "\x1b[36m", "\x1b[31m"],
         }
     }
 
@@ -62,51 +109,480 @@ impl ColorScheme {
             block_device: "\x1b[94m",
             char_device: "\x1b[93m",
             broken_symlink: "\x1b[91m",
+            broken_target: "\x1b[2;9;91m",
             git_new: "\x1b[92m",
             git_modified: "\x1b[94m",
             git_deleted: "\x1b[91m",
             git_renamed: "\x1b[93m",
             git_untracked: "\x1b[90m",
             git_ignored: "\x1b[90m",
+            git_conflicted: "\x1b[1;91m",
+            git_staged: "\x1b[32m",
+            git_unstaged: "\x1b[31m",
+            rename_source: "\x1b[2m",
+            highlight: "\x1b[1;7m",
+            blame: "\x1b[2m",
+            new_badge: "\x1b[1;92m",
+            links: "\x1b[2m",
+            inode: "\x1b[95m",
+            size_small: "\x1b[92m",
+            size_medium: "\x1b[93m",
+            size_large: "\x1b[91m",
+            size_huge: "\x1b[1;91m",
+            mime_text: "\x1b[30m",
+            mime_image: "\x1b[35m",
+            mime_audio: "\x1b[36m",
+            mime_video: "\x1b[95m",
+            mime_application: "\x1b[33m",
+            depth: ["\x1b[34m", "\x1b[32m", "\x1b[33m", "\x1b[35m", "\x1b[36m", "\x1b[31m"],
         }
     }
+
+    /// No escape sequences at all, for output that is not coloured.
+    const fn plain() -> Self {
+        Self {
+            reset: "",
+            dir: "",
+            symlink: "",
+            executable: "",
+            file: "",
+            pipe: "",
+            socket: "",
+            block_device: "",
+            char_device: "",
+            broken_symlink: "",
+            broken_target: "",
+            git_new: "",
+            git_modified: "",
+            git_deleted: "",
+            git_renamed: "",
+            git_untracked: "",
+            git_ignored: "",
+            git_conflicted: "",
+            git_staged: "",
+            git_unstaged: "",
+            rename_source: "",
+            highlight: "",
+            blame: "",
+            new_badge: "",
+            links: "",
+            inode: "",
+            size_small: "",
+            size_medium: "",
+            size_large: "",
+            size_huge: "",
+            mime_text: "",
+            mime_image: "",
+            mime_audio: "",
+            mime_video: "",
+            mime_application: "",
+            depth: [""; 6],
+        }
+    }
+
+    /// Every field with its name, in declaration order.  The `depth`
+    /// palette is listed one level at a time.
+    fn fields(&self) -> [(&'static str, &'static str); 41] {
+        [
+            ("reset", self.reset),
+            ("dir", self.dir),
+            ("symlink", self.symlink),
+            ("executable", self.executable),
+            ("file", self.file),
+            ("pipe", self.pipe),
+            ("socket", self.socket),
+            ("block_device", self.block_device),
+            ("char_device", self.char_device),
+            ("broken_symlink", self.broken_symlink),
+            ("broken_target", self.broken_target),
+            ("git_new", self.git_new),
+            ("git_modified", self.git_modified),
+            ("git_deleted", self.git_deleted),
+            ("git_renamed", self.git_renamed),
+            ("git_untracked", self.git_untracked),
+            ("git_ignored", self.git_ignored),
+            ("git_conflicted", self.git_conflicted),
+            ("git_staged", self.git_staged),
+            ("git_unstaged", self.git_unstaged),
+            ("rename_source", self.rename_source),
+            ("highlight", self.highlight),
+            ("blame", self.blame),
+            ("new_badge", self.new_badge),
+            ("links", self.links),
+            ("inode", self.inode),
+            ("size_small", self.size_small),
+            ("size_medium", self.size_medium),
+            ("size_large", self.size_large),
+            ("size_huge", self.size_huge),
+            ("mime_text", self.mime_text),
+            ("mime_image", self.mime_image),
+            ("mime_audio", self.mime_audio),
+            ("mime_video", self.mime_video),
+            ("mime_application", self.mime_application),
+            ("depth_1", self.depth[0]),
+            ("depth_2", self.depth[1]),
+            ("depth_3", self.depth[2]),
+            ("depth_4", self.depth[3]),
+            ("depth_5", self.depth[4]),
+            ("depth_6", self.depth[5]),
+        ]
+    }
+
+    /// Colour for the tree level `level`, counting the top entries as 1.
+    fn depth_color(&self, level: usize) -> &'static str {
+        self.depth[level.saturating_sub(1) % self.depth.len()]
+    }
+
+    fn mime_color(&self, category: mime::Category) -> &'static str {
+        match category {
+            mime::Category::Text => self.mime_text,
+            mime::Category::Image => self.mime_image,
+            mime::Category::Audio => self.mime_audio,
+            mime::Category::Video => self.mime_video,
+            mime::Category::Application => self.mime_application,
+        }
+    }
+
+    /// The colour for a size, stepping up at each unit of `base` (1000 with
+    /// `--si`, else 1024) so it changes where the unit suffix does.
+    fn size_color(&self, size: u64, base: u64) -> &'static str {
+        if size < base {
+            self.size_small
+        } else if size < base * base {
+            self.size_medium
+        } else if size < base * base * base {
+            self.size_large
+        } else {
+            self.size_huge
+        }
+    }
+}
+
+/// Primary sort order, applied after any directory grouping.  Names break
+/// ties for every key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    /// Newest modification time first.
+    Time,
+    /// Largest first.
+    Size,
+    /// Shortest name first.
+    Length,
+    /// Grouped by extension, entries without one first.
+    Extension,
+    /// Like name, but runs of digits compare as numbers.
+    Version,
+    /// Grouped by git state, whatever needs attention first.
+    Git,
+    /// Unsorted, in whatever order the filesystem returns.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DotfilesPosition {
+    First,
+    Last,
+    Mixed,
+}
+
+/// When to colour the output, picked with `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    /// Only when stdout is a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Which timestamp `--time` shows in long listings and sorts by with `-t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeField {
+    Modified,
+    Accessed,
+    /// Last status change (ctime).
+    Changed,
+    Birth,
+}
+
+/// How `--time-style` renders timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeStyle {
+    /// `YYYY-MM-DD HH:MM`.
+    Default,
+    /// Full ISO 8601 with seconds and the UTC offset.
+    Iso,
+    /// Age such as `3d`.
+    Relative,
+}
+
+/// Which glyphs stand for each kind of entry, picked with `--icon-set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IconSet {
+    Emoji,
+    /// Private-use glyphs from a patched Nerd Font.
+    Nerd,
+    /// Short tags such as `[d]`.
+    Ascii,
+}
+
+/// When to put an icon in front of entry names, picked with `--icons`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IconMode {
+    /// Only when stdout is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Where the git status letter goes relative to the entry name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GitPosition {
+    Leading,
+    Trailing,
+}
+
+/// What colours an entry's name when git status is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameColor {
+    /// Always the file type colour; git state only shows in the indicator.
+    Type,
+    /// Any git state overrides the type colour.
+    Git,
+    /// Only modified and conflicted entries take the git colour.
+    GitOverModified,
+}
+
+/// How symlinks whose target does not exist are presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BrokenLinks {
+    /// Own colour and count in the report.
+    Highlight,
+    /// Treated like any other symlink.
+    Plain,
+    /// Left out of the listing altogether.
+    Hide,
+}
+
+/// Permission predicate mirroring `find -perm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PermFilter {
+    /// `/MASK`: any of the bits are set.
+    Any(u32),
+    /// `-MASK`: all of the bits are set.
+    All(u32),
+    /// `MASK`: the permission bits are exactly this value.
+    Exact(u32),
 }
 
 #[derive(Clone)]
 struct Options {
     one_per_line: bool,
+    /// Fill the grid row by row instead of column by column, like `ls -x`.
+    across: bool,
+    /// Terminal width the grid is laid out for, instead of the detected one.
+    width: Option<usize>,
+    entry_terminator: Option<String>,
+    page_size: Option<usize>,
+    /// Print a `-1` listing as the directory is read, unsorted.
+    stream: bool,
+    /// Clear the screen and list the paths again at this interval.
+    watch: Option<Duration>,
     all: bool,
     almost_all: bool,
+    strict_utf8: bool,
+    dedup: bool,
+    /// Number the entries of a listing that are hard links of each other.
+    hard_links: bool,
     dirs_only: bool,
     files_only: bool,
+    perm_filter: Option<PermFilter>,
+    /// Only entries with this uid / gid (`--owner`, `--group`).
+    owner_filter: Option<u32>,
+    group_filter: Option<u32>,
+    broken_links: BrokenLinks,
     long: bool,
+    /// Show what symlinks point at instead of the links themselves.
+    dereference: bool,
+    /// Put the numeric mode in front of the permission string.
+    octal_perms: bool,
+    /// Show numeric uid and gid in the long format instead of names.
+    numeric_ids: bool,
+    long0: bool,
+    blame: bool,
+    /// Badge files first committed to git within this window.
+    new_within: Option<Duration>,
     report: bool,
+    /// Tally the tree's entries by icon after a `--tree` listing.
+    tree_summary_icons: bool,
+    /// Tint tree connectors by their depth.
+    depth_colors: bool,
+    /// Directories given with `--fd`, as the `/dev/fd` path that stands for
+    /// them among the paths to list and the descriptor they are read through.
+    fd_dirs: Vec<(PathBuf, u32)>,
+    stat: bool,
+    summary_json: bool,
+    json: bool,
+    csv: bool,
+    compact: bool,
+    with_header: bool,
+    /// List each subdirectory in turn under its own header, like `ls -R`.
+    recursive: bool,
     tree_depth: Option<usize>,
+    /// Entries shown per directory in a tree before the rest are elided.
+    max_entries_per_dir: Option<usize>,
+    /// Print the tree walk as paths relative to the root, one per line.
+    flatten: bool,
     git_status: bool,
+    git_cache: bool,
+    /// Work out git status from the index where it can instead of running
+    /// `git status`; off with `--git-shell-out`.
+    git_direct: bool,
+    /// Leave out whatever the repository's ignore rules match.
+    git_ignore: bool,
+    git_position: GitPosition,
+    /// Show the staged and unstaged git columns instead of one letter.
+    git_columns: bool,
+    name_color: NameColor,
     sort_dirs_first: bool,
     sort_files_first: bool,
-    sort_time: bool,
+    sort_key: SortKey,
+    reverse: bool,
+    group_reverse: bool,
+    dotfiles: DotfilesPosition,
     human_readable: bool,
+    /// Human readable sizes in powers of 1000 instead of 1024.
+    si: bool,
+    /// Show directories with the size of everything in them.
+    total_size: bool,
+    /// Show times in UTC rather than the local timezone.
+    utc: bool,
+    time_style: TimeStyle,
+    time_field: TimeField,
+    uniform_size_unit: bool,
+    /// Print long-format sizes as a number and a separate unit column.
+    bytes_aligned: bool,
+    /// Add a device column to the long format.
+    show_device: bool,
+    /// Show the mount point in that column instead of the device number.
+    resolve_mounts: bool,
+    /// Add a column with the space each entry takes on disk.
+    blocks: bool,
+    /// Bytes per block in the `total` line of a long listing.
+    block_size: u64,
+    /// Start each entry with its inode number.
+    inode: bool,
+    colorful_columns: bool,
+    classify_mime: bool,
+    /// Append a type indicator such as `/` to each name, like `ls -F`.
+    classify: bool,
+    smart_dir_icons: bool,
+    icon_set: IconSet,
+    /// Keep listings to plain ASCII: tree connectors and escaped names.
+    ascii: bool,
+    icon_mode: IconMode,
+    highlight_patterns: Vec<String>,
+    /// `--match` globs; when any are given, only names matching one show.
+    match_patterns: Vec<String>,
+    /// `--ignore` globs, which win over `--match`.
+    ignore_patterns: Vec<String>,
+    /// Make leading and trailing spaces in names visible.
+    mark_spaces: bool,
+    resolve_links: bool,
+    progress: bool,
+    /// Draw `--progress` even when stderr is not a terminal.
+    progress_always: bool,
+    /// Threads used to stat large directories; `None` means one per CPU.
+    threads: Option<usize>,
     color_scheme: ColorScheme,
+    color_mode: ColorMode,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             one_per_line: false,
+            across: false,
+            width: None,
+            entry_terminator: None,
+            page_size: None,
+            stream: false,
+            watch: None,
             all: false,
             almost_all: false,
+            strict_utf8: false,
+            dedup: false,
+            hard_links: false,
             dirs_only: false,
             files_only: false,
+            perm_filter: None,
+            owner_filter: None,
+            group_filter: None,
+            broken_links: BrokenLinks::Highlight,
             long: false,
+            dereference: false,
+            octal_perms: false,
+            numeric_ids: false,
+            long0: false,
+            blame: false,
+            new_within: None,
             report: false,
+            tree_summary_icons: false,
+            depth_colors: false,
+            fd_dirs: Vec::new(),
+            stat: false,
+            summary_json: false,
+            json: false,
+            csv: false,
+            compact: false,
+            with_header: false,
+            recursive: false,
             tree_depth: None,
+            max_entries_per_dir: None,
+            flatten: false,
             git_status: false,
+            git_ignore: false,
+            git_cache: false,
+            git_direct: true,
+            git_position: GitPosition::Leading,
+            git_columns: false,
+            name_color: NameColor::Type,
             sort_dirs_first: false,
             sort_files_first: false,
-            sort_time: false,
+            sort_key: SortKey::Name,
+            reverse: false,
+            group_reverse: false,
+            dotfiles: DotfilesPosition::First,
             human_readable: true,
+            si: false,
+            total_size: false,
+            utc: false,
+            time_style: TimeStyle::Default,
+            time_field: TimeField::Modified,
+            uniform_size_unit: false,
+            bytes_aligned: false,
+            show_device: false,
+            resolve_mounts: false,
+            blocks: false,
+            block_size: 1024,
+            inode: false,
+            colorful_columns: false,
+            classify_mime: false,
+            classify: false,
+            smart_dir_icons: false,
+            icon_set: IconSet::Emoji,
+            ascii: false,
+            icon_mode: IconMode::Always,
+            highlight_patterns: Vec::new(),
+            match_patterns: Vec::new(),
+            ignore_patterns: Vec::new(),
+            mark_spaces: true,
+            resolve_links: false,
+            progress: false,
+            progress_always: false,
+            threads: None,
             color_scheme: ColorScheme::dark(),
+            color_mode: ColorMode::Auto,
         }
     }
 }
@@ -120,14 +596,173 @@ enum GitState {
     TypeChanged,
     Untracked,
     Ignored,
+    /// Unmerged paths left by a conflicted merge.
+    Conflicted,
     None,
 }
 
+/// The two porcelain columns of `git status` kept apart: what is staged in
+/// the index and what is changed in the working tree only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GitFlags {
+    index: GitState,
+    worktree: GitState,
+}
+
+impl GitFlags {
+    const CLEAN: Self = Self { index: GitState::None, worktree: GitState::None };
+}
+
+/// What `git status` reported for one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GitEntry {
+    state: GitState,
+    flags: GitFlags,
+    /// The previous path of a rename, relative to the top of the repository.
+    renamed_from: Option<PathBuf>,
+}
+
+/// Git states keyed by absolute, canonical path, so one `git status` run
+/// serves every directory of the repository.
+type GitMap = HashMap<PathBuf, GitEntry>;
+
 struct EntryInfo {
-    entry: DirEntry,
+    path: PathBuf,
+    name: OsString,
     metadata: Metadata,
     icon: &'static str,
     git_state: GitState,
+    git_flags: GitFlags,
+    renamed_from: Option<PathBuf>,
+    /// Further hard links to this inode left out by `--dedup`.
+    hidden_links: usize,
+    /// Number shared with the other hard links to this inode in the listing,
+    /// with `--hard-links`.
+    link_group: Option<usize>,
+    /// First committed to git within `--new-within`.
+    is_new: bool,
+    /// Size of everything below a directory with `--total-size`.
+    total_size: Option<u64>,
+}
+
+/// Pauses a listing every `--page` entries until Enter is pressed.  Paging
+/// is only active when stdout is a terminal; otherwise every entry goes out
+/// in one go.
+struct Pager {
+    page_size: usize,
+    total: usize,
+    printed: usize,
+}
+
+impl Pager {
+    fn new(opts: &Options, total: usize) -> Self {
+        let page_size = match opts.page_size {
+            Some(n) if io::stdout().is_terminal() => n,
+            _ => 0,
+        };
+        Self { page_size, total, printed: 0 }
+    }
+
+    /// Entries that fit on one page: all of them when not paging.
+    fn page_len(&self) -> usize {
+        if self.page_size == 0 { self.total.max(1) } else { self.page_size }
+    }
+
+    /// Counts `count` more entries as printed and returns the footer to show
+    /// if that ends a page other than the last.
+    fn advance(&mut self, count: usize) -> Option<String> {
+        self.printed += count;
+        if self.page_size == 0 || !self.printed.is_multiple_of(self.page_size) || self.printed >= self.total {
+            return None;
+        }
+        Some(format!(
+            "-- page {}/{}, {} more --",
+            self.printed / self.page_size,
+            self.total.div_ceil(self.page_size),
+            self.total - self.printed
+        ))
+    }
+
+    fn after_entries(&mut self, count: usize, out: &mut dyn Write) -> io::Result<()> {
+        let footer = match self.advance(count) {
+            Some(footer) => footer,
+            None => return Ok(()),
+        };
+        write!(out, "{}", footer)?;
+        out.flush()?;
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        // A terminal echoes the Enter, so the footer is one line up by now.
+        if io::stdin().is_terminal() {
+            write!(out, "\x1b[1A")?;
+        }
+        write!(out, "\r\x1b[K")?;
+        Ok(())
+    }
+}
+
+/// Column widths shared by every row of a long listing.
+#[derive(Default)]
+struct LongLayout {
+    link_w: usize,
+    uid_w: usize,
+    gid_w: usize,
+    size_w: usize,
+    /// Width of the unit part of sizes with `--bytes-aligned`.
+    size_unit_w: usize,
+    /// Unit every size is rendered in with `--uniform-size-unit`.
+    size_unit: Option<usize>,
+    /// Last commit author per entry path with `--blame`.
+    blame: Option<HashMap<PathBuf, String>>,
+    blame_w: usize,
+    /// Device column per entry path with `--show-device`.
+    devices: Option<HashMap<PathBuf, String>>,
+    device_w: usize,
+    /// Width of the `--blocks` column.
+    blocks_w: usize,
+    /// Width of the `-i` column.
+    inode_w: usize,
+    /// Owner and group names looked up so far, unless `-n` is given.
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl LongLayout {
+    fn format_size(&self, size: u64, opts: &Options) -> String {
+        let (number, unit) = self.size_parts(size, opts);
+        format!("{}{}", number, unit)
+    }
+
+    /// The number and unit of a size, kept apart for `--bytes-aligned`.
+    fn size_parts(&self, size: u64, opts: &Options) -> (String, &'static str) {
+        match self.size_unit {
+            Some(idx) => size_parts_in_unit(size, idx, opts.si),
+            None if opts.human_readable => size_parts_in_unit(size, size_unit_for(size, opts.si), opts.si),
+            None => (size.to_string(), ""),
+        }
+    }
+
+    /// Looks up the names for `uid` and `gid` once per listing.  Ids with no
+    /// passwd or group entry keep their number.
+    fn resolve_ids(&mut self, uid: u32, gid: u32, opts: &Options) {
+        if opts.numeric_ids {
+            return;
+        }
+        self.users
+            .entry(uid)
+            .or_insert_with(|| user_name(uid).unwrap_or_else(|| uid.to_string()));
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| group_name(gid).unwrap_or_else(|| gid.to_string()));
+    }
+
+    fn user(&self, uid: u32) -> String {
+        self.users.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+    }
+
+    fn group(&self, gid: u32) -> String {
+        self.groups.get(&gid).cloned().unwrap_or_else(|| gid.to_string())
+    }
 }
 
 #[derive(Default)]
@@ -140,25 +775,214 @@ struct Counts {
     block_devices: usize,
     char_devices: usize,
     broken_symlinks: usize,
+    /// Combined length of the regular files counted.
+    total_bytes: u64,
+}
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Spinner on stderr that is redrawn at most every `PROGRESS_INTERVAL`
+/// while entries are scanned, and erased before anything goes to stdout.
+struct Progress {
+    enabled: bool,
+    processed: usize,
+    frame: usize,
+    last_draw: Instant,
+    visible: bool,
+}
+
+impl Progress {
+    fn new(enabled: bool, always: bool) -> Self {
+        Self {
+            enabled: enabled && (always || io::stderr().is_terminal()),
+            processed: 0,
+            frame: 0,
+            last_draw: Instant::now(),
+            visible: false,
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.processed += 1;
+        let now = Instant::now();
+        if now.duration_since(self.last_draw) < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_draw = now;
+        self.frame = (self.frame + 1) % PROGRESS_FRAMES.len();
+        eprint!("\r\x1b[K{} {} entries scanned", PROGRESS_FRAMES[self.frame], self.processed);
+        io::stderr().flush().unwrap_or(());
+        self.visible = true;
+    }
+
+    fn clear(&mut self) {
+        if self.visible {
+            eprint!("\r\x1b[K");
+            io::stderr().flush().unwrap_or(());
+            self.visible = false;
+        }
+    }
 }
 
+/// Set when an entry had to be skipped in a way scripts should notice.
+static EXIT_FAILURE: AtomicBool = AtomicBool::new(false);
+
 fn main() {
     let mut opts = Options::default();
     let mut paths: Vec<PathBuf> = Vec::new();
-    let mut args = env::args().skip(1);
+    let mut list_colors = false;
+    let mut stdin_names = false;
+    let args = env::args_os().skip(1);
     
-    while let Some(arg) = args.next() {
+    for arg in args {
+        let arg = match arg.into_string() {
+            Ok(arg) => arg,
+            // Anything that is not UTF-8 can only be a path.
+            Err(path) => {
+                paths.push(PathBuf::from(path));
+                continue;
+            }
+        };
         match arg.as_str() {
             "-1" => opts.one_per_line = true,
+            "-x" | "--across" => opts.across = true,
+            s if s.starts_with("--width=") => {
+                let val = &s["--width=".len()..];
+                match val.parse::<usize>() {
+                    Ok(n) => opts.width = Some(n),
+                    Err(_) => {
+                        eprintln!("Invalid value for --width: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--stream" => opts.stream = true,
+            "--watch" => opts.watch = Some(Duration::from_secs(2)),
+            s if s.starts_with("--watch=") => {
+                let val = &s["--watch=".len()..];
+                match parse_duration(val) {
+                    Some(d) if !d.is_zero() => opts.watch = Some(d),
+                    _ => {
+                        eprintln!("Invalid interval for --watch: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--page=") => {
+                let val = &s["--page=".len()..];
+                match val.parse::<usize>() {
+                    Ok(n) if n > 0 => opts.page_size = Some(n),
+                    _ => {
+                        eprintln!("Invalid page size for --page: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--entry-terminator=") => {
+                opts.entry_terminator = Some(unescape(&s["--entry-terminator=".len()..]));
+            }
             "-a" | "--all" => opts.all = true,
             "-A" | "--almost-all" => {
                 opts.all = true;
                 opts.almost_all = true;
             }
+            "--strict-utf8" => opts.strict_utf8 = true,
+            "--dedup" => opts.dedup = true,
+            "--hard-links" => opts.hard_links = true,
             "-d" | "--dirs" => opts.dirs_only = true,
             "-f" | "--files" => opts.files_only = true,
             "-l" | "--long" => opts.long = true,
+            "-L" | "--dereference" => opts.dereference = true,
+            "--octal" | "--octal-permissions" => opts.octal_perms = true,
+            "-n" | "--numeric-uid-gid" => {
+                opts.long = true;
+                opts.numeric_ids = true;
+            }
+            "--long0" => opts.long0 = true,
+            "--blame" => opts.blame = true,
+            "--show-device" => opts.show_device = true,
+            "--resolve-mounts" => {
+                opts.show_device = true;
+                opts.resolve_mounts = true;
+            }
+            "--blocks" => opts.blocks = true,
+            s if s.starts_with("--block-size=") => {
+                let val = &s["--block-size=".len()..];
+                match val.parse::<u64>() {
+                    Ok(n) if n > 0 => opts.block_size = n,
+                    _ => {
+                        eprintln!("Invalid value for --block-size: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-i" | "--inode" => opts.inode = true,
+            s if s.starts_with("--new-within=") => {
+                let val = &s["--new-within=".len()..];
+                match parse_duration(val) {
+                    Some(d) => opts.new_within = Some(d),
+                    None => {
+                        eprintln!("Invalid value for --new-within: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--broken-links=") => {
+                let val = &s["--broken-links=".len()..];
+                opts.broken_links = match val {
+                    "highlight" => BrokenLinks::Highlight,
+                    "plain" => BrokenLinks::Plain,
+                    "hide" => BrokenLinks::Hide,
+                    _ => {
+                        eprintln!("Invalid value for --broken-links: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            s if s.starts_with("--perm=") => {
+                let val = &s["--perm=".len()..];
+                match parse_perm_filter(val) {
+                    Some(filter) => opts.perm_filter = Some(filter),
+                    None => {
+                        eprintln!("Invalid mode for --perm: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--owner=") => {
+                let val = &s["--owner=".len()..];
+                match val.parse::<u32>().ok().or_else(|| uid_for_name(val)) {
+                    Some(uid) => opts.owner_filter = Some(uid),
+                    None => {
+                        eprintln!("Invalid value for --owner: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--group=") => {
+                let val = &s["--group=".len()..];
+                match val.parse::<u32>().ok().or_else(|| gid_for_name(val)) {
+                    Some(gid) => opts.group_filter = Some(gid),
+                    None => {
+                        eprintln!("Invalid value for --group: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
             "--report" => opts.report = true,
+            "--tree-summary-icons" => opts.tree_summary_icons = true,
+            "--depth-colors" => opts.depth_colors = true,
+            "--stat" => opts.stat = true,
+            "--summary-json" => opts.summary_json = true,
+            "--json" => opts.json = true,
+            "--csv" => opts.csv = true,
+            "--compact" => opts.compact = true,
+            "--with-header" => opts.with_header = true,
+            "-R" | "--recursive" => opts.recursive = true,
             s if s.starts_with("--tree") => {
                 if s == "--tree" {
                     opts.tree_depth = Some(3);
@@ -166,12 +990,8 @@ fn main() {
                     let val = &s[eq_idx + 1..];
                     if val.is_empty() {
                         opts.tree_depth = Some(3);
-                    } else if let Ok(num) = val.parse::<isize>() {
-                        if num <= 0 {
-                            opts.tree_depth = None;
-                        } else {
-                            opts.tree_depth = Some(num as usize);
-                        }
+                    } else if let Some(depth) = parse_tree_depth(val) {
+                        opts.tree_depth = Some(depth);
                     } else {
                         eprintln!("Invalid depth for --tree: {}", val);
                         std::process::exit(1);
@@ -181,13 +1001,272 @@ fn main() {
                     std::process::exit(1);
                 }
             }
+            s if s.starts_with("--depth=") => {
+                let val = &s["--depth=".len()..];
+                match parse_tree_depth(val) {
+                    Some(depth) => opts.tree_depth = Some(depth),
+                    None => {
+                        eprintln!("Invalid depth for --depth: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--max-entries-per-dir=") => {
+                let val = &s["--max-entries-per-dir=".len()..];
+                match val.parse::<usize>() {
+                    Ok(n) if n > 0 => opts.max_entries_per_dir = Some(n),
+                    _ => {
+                        eprintln!("Invalid value for --max-entries-per-dir: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--flatten" => {
+                opts.flatten = true;
+                opts.tree_depth = Some(usize::MAX);
+            }
+            s if s.starts_with("--flatten=") => {
+                let val = &s["--flatten=".len()..];
+                match val.parse::<usize>() {
+                    Ok(num) if num > 0 => {
+                        opts.flatten = true;
+                        opts.tree_depth = Some(num);
+                    }
+                    _ => {
+                        eprintln!("Invalid depth for --flatten: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
             "--gs" | "--git-status" => opts.git_status = true,
+            "--git-ignore" => opts.git_ignore = true,
+            "--git-columns" => opts.git_columns = true,
+            "--git-cache" => opts.git_cache = true,
+            "--no-git-shell-out" => opts.git_direct = true,
+            "--git-shell-out" => opts.git_direct = false,
+            s if s.starts_with("--git-position=") => {
+                let val = &s["--git-position=".len()..];
+                opts.git_position = match val {
+                    "leading" => GitPosition::Leading,
+                    "trailing" => GitPosition::Trailing,
+                    _ => {
+                        eprintln!("Invalid value for --git-position: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            s if s.starts_with("--name-color=") => {
+                let val = &s["--name-color=".len()..];
+                opts.name_color = match val {
+                    "type" => NameColor::Type,
+                    "git" => NameColor::Git,
+                    "git-over-modified" => NameColor::GitOverModified,
+                    _ => {
+                        eprintln!("Invalid value for --name-color: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
             "--sd" | "--sort-dirs" | "--group-directories-first" => opts.sort_dirs_first = true,
-            "--sf" | "--sort-files" => opts.sort_files_first = true,
-            "-t" => opts.sort_time = true,
+            "--sf" | "--sort-files" | "--group-directories-last" => opts.sort_files_first = true,
+            "-t" => opts.sort_key = SortKey::Time,
+            "-U" => opts.sort_key = SortKey::None,
+            "-S" => opts.sort_key = SortKey::Size,
+            s if s.starts_with("--sort=") => {
+                let val = &s["--sort=".len()..];
+                opts.sort_key = match val {
+                    "name" => SortKey::Name,
+                    "time" => SortKey::Time,
+                    "size" => SortKey::Size,
+                    "length" => SortKey::Length,
+                    "extension" => SortKey::Extension,
+                    "version" => SortKey::Version,
+                    "git" | "git-status" => {
+                        opts.git_status = true;
+                        SortKey::Git
+                    }
+                    "none" => SortKey::None,
+                    _ => {
+                        eprintln!("Invalid value for --sort: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "-r" | "--reverse" => opts.reverse = true,
+            "--group-reverse" => opts.group_reverse = true,
+            s if s.starts_with("--dotfiles=") => {
+                let val = &s["--dotfiles=".len()..];
+                opts.dotfiles = match val {
+                    "first" => DotfilesPosition::First,
+                    "last" => DotfilesPosition::Last,
+                    "mixed" => DotfilesPosition::Mixed,
+                    _ => {
+                        eprintln!("Invalid value for --dotfiles: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
             "--light" => opts.color_scheme = ColorScheme::light(),
             "--dark" => opts.color_scheme = ColorScheme::dark(),
+            s if s.starts_with("--color=") => {
+                let val = &s["--color=".len()..];
+                opts.color_mode = match val {
+                    "auto" => ColorMode::Auto,
+                    "always" => ColorMode::Always,
+                    "never" => ColorMode::Never,
+                    _ => {
+                        eprintln!("Invalid value for --color: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
             "--non-human-readable" => opts.human_readable = false,
+            "--si" => opts.si = true,
+            "--total-size" | "--du" => opts.total_size = true,
+            "--utc" => opts.utc = true,
+            s if s.starts_with("--time=") => {
+                let val = &s["--time=".len()..];
+                opts.time_field = match val {
+                    "mod" | "modification" => TimeField::Modified,
+                    "access" | "atime" => TimeField::Accessed,
+                    "change" | "status" | "ctime" => TimeField::Changed,
+                    "birth" | "creation" => TimeField::Birth,
+                    _ => {
+                        eprintln!("Invalid value for --time: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            s if s.starts_with("--time-style=") => {
+                let val = &s["--time-style=".len()..];
+                opts.time_style = match val {
+                    "default" | "long-iso" => TimeStyle::Default,
+                    "iso" | "full-iso" => TimeStyle::Iso,
+                    "relative" => TimeStyle::Relative,
+                    _ => {
+                        eprintln!("Invalid value for --time-style: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--uniform-size-unit" => opts.uniform_size_unit = true,
+            "--bytes-aligned" => opts.bytes_aligned = true,
+            "--colorful-columns" => opts.colorful_columns = true,
+            "--progress" => opts.progress = true,
+            s if s.starts_with("--progress=") => {
+                let val = &s["--progress=".len()..];
+                (opts.progress, opts.progress_always) = match val {
+                    "auto" => (true, false),
+                    "always" => (true, true),
+                    "never" => (false, false),
+                    _ => {
+                        eprintln!("Invalid value for --progress: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            s if s.starts_with("--threads=") => {
+                let val = &s["--threads=".len()..];
+                match val.parse::<usize>() {
+                    Ok(n) if n > 0 => opts.threads = Some(n),
+                    _ => {
+                        eprintln!("Invalid value for --threads: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-F" | "--classify" => opts.classify = true,
+            s if s.starts_with("--classify=") => {
+                let val = &s["--classify=".len()..];
+                opts.classify_mime = match val {
+                    "mime" => true,
+                    "none" => false,
+                    _ => {
+                        eprintln!("Invalid value for --classify: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--resolve-links" => opts.resolve_links = true,
+            "--no-mark-spaces" => opts.mark_spaces = false,
+            "--smart-dir-icons" => opts.smart_dir_icons = true,
+            "--ascii-icons" => opts.icon_set = IconSet::Ascii,
+            s if s.starts_with("--icon-set=") => {
+                let val = &s["--icon-set=".len()..];
+                opts.icon_set = match val {
+                    "emoji" => IconSet::Emoji,
+                    "nerd" => IconSet::Nerd,
+                    "ascii" => IconSet::Ascii,
+                    _ => {
+                        eprintln!("Invalid value for --icon-set: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--ascii" => {
+                opts.ascii = true;
+                opts.icon_set = IconSet::Ascii;
+            }
+            "--no-icons" => opts.icon_mode = IconMode::Never,
+            s if s.starts_with("--icons=") => {
+                let val = &s["--icons=".len()..];
+                opts.icon_mode = match val {
+                    "auto" => IconMode::Auto,
+                    "always" => IconMode::Always,
+                    "never" => IconMode::Never,
+                    _ => {
+                        eprintln!("Invalid value for --icons: {}", val);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            s if s.starts_with("--highlight=") => {
+                opts.highlight_patterns.push(s["--highlight=".len()..].to_string());
+            }
+            s if s.starts_with("--match=") => {
+                opts.match_patterns.push(s["--match=".len()..].to_string());
+            }
+            s if s.starts_with("--ignore=") => {
+                opts.ignore_patterns.push(s["--ignore=".len()..].to_string());
+            }
+            s if s.starts_with("--files0-from=") => {
+                let source = &s["--files0-from=".len()..];
+                match read_files0_from(source) {
+                    Ok(list) => paths.extend(list),
+                    Err(e) => {
+                        eprintln!("rdir: cannot read {}: {}", source, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            s if s.starts_with("--fd=") => {
+                let val = &s["--fd=".len()..];
+                match val.parse::<u32>() {
+                    Ok(fd) => match check_fd_dir(fd) {
+                        Ok(()) => {
+                            let path = PathBuf::from(format!("/dev/fd/{}", fd));
+                            opts.fd_dirs.push((path.clone(), fd));
+                            paths.push(path);
+                        }
+                        Err(e) => {
+                            eprintln!("rdir: cannot list fd {}: {}", fd, e);
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(_) => {
+                        eprintln!("Invalid file descriptor for --fd: {}", val);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--list-colors" => list_colors = true,
+            "--stdin-names" => stdin_names = true,
+            "--self-test" => {
+                if !self_test(visible_len) {
+                    std::process::exit(1);
+                }
+                return;
+            }
             "--help" | "-h" => {
                 print_help();
                 return;
@@ -206,547 +1285,2841 @@ fn main() {
         }
     }
     
+    let use_color = match opts.color_mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        // An empty NO_COLOR does not count, as https://no-color.org asks.
+        ColorMode::Auto => {
+            io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        }
+    };
+    if !use_color {
+        opts.color_scheme = ColorScheme::plain();
+    }
+    if opts.icon_mode == IconMode::Auto {
+        opts.icon_mode = if io::stdout().is_terminal() { IconMode::Always } else { IconMode::Never };
+    }
+    
     if paths.is_empty() {
         paths.push(PathBuf::from("."));
     }
     
-    let multiple = paths.len() > 1;
-    for (idx, path) in paths.iter().enumerate() {
-        if multiple {
-            println!("{}:", path.display());
-        }
-
-        // ADDED: handle files distinctly
-        if let Ok(metadata) = fs::metadata(path) {
-            if metadata.is_file() {
-                // Use existing utility functions for formats
-                let file_type = metadata.file_type();
-                let perm = perm_string(&file_type, &metadata);
-                let size_str = format_size(metadata.len(), opts.human_readable);
-                let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-                let time_str = format_time(mtime);
-                println!("File: {}", path.display());
-                println!("Permissions: {}", perm);
-                println!("Size: {}", size_str);
-                println!("Last Modified: {}", time_str);
-                // Print a divider if multiple
-                if multiple && idx + 1 < paths.len() {
-                    println!();
-                }
-                continue; // Don't try to list as directory
-            }
+    // One lock and buffer for the whole run.  A terminal still sees each
+    // line as it is written, since stdout is line buffered there anyway.
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = if stdout.is_terminal() {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(io::BufWriter::new(stdout.lock()))
+    };
+    let result = if list_colors {
+        print_colors(&opts.color_scheme, &mut out)
+    } else if stdin_names {
+        print_stdin_names(&opts, &mut out)
+    } else if let Some(interval) = opts.watch {
+        watch_paths(&paths, &opts, &mut out, &mut detect_terminal_width, &mut || {
+            std::thread::sleep(interval);
+            true
+        })
+    } else {
+        list_paths(&paths, &opts, &mut out)
+    };
+    let result = result.and_then(|()| out.flush());
+    drop(out);
+    if let Err(e) = result {
+        // A closed pipe (`rdir | head`) just means nobody wants the rest.
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("rdir: {}", e);
+            EXIT_FAILURE.store(true, AtomicOrdering::Relaxed);
         }
+    }
+    
+    if EXIT_FAILURE.load(AtomicOrdering::Relaxed) {
+        io::stdout().flush().unwrap_or(());
+        std::process::exit(1);
+    }
+}
 
-        let mut counts = Counts::default();
-        
-        if let Some(depth) = opts.tree_depth {
-            let git_map = if opts.git_status {
-                git_statuses(path)
-            } else {
-                HashMap::new()
-            };
-            print_tree(path, path, "".to_string(), depth, &opts, &git_map, &mut counts);
-        } else if opts.tree_depth.is_some() {
-            let git_map = if opts.git_status {
-                git_statuses(path)
-            } else {
-                HashMap::new()
-            };
-            print_tree(path, path, "".to_string(), usize::MAX, &opts, &git_map, &mut counts);
-        } else {
-            list_dir(path, &opts, &mut counts);
+/// Lists every path given on the command line, in order.
+fn list_paths(paths: &[PathBuf], opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    let named = paths.iter().filter(|path| !rejects_name(path, opts));
+    // Files named directly are listed together first, as ls does, then each
+    // directory in turn.
+    let (files, dirs): (Vec<&PathBuf>, Vec<&PathBuf>) = if opts.stat {
+        (Vec::new(), named.collect())
+    } else {
+        named.partition(|path| !opts.fd_dirs.iter().any(|(fd_path, _)| fd_path == *path) && names_file(path))
+    };
+    if !files.is_empty() {
+        list_files(&files, opts, out)?;
+        if !dirs.is_empty() && !opts.compact && !opts.summary_json && !opts.json && !opts.csv {
+            writeln!(out)?;
         }
-        
-        if opts.report {
-            print_report(&counts);
+    }
+    
+    let multiple = paths.len() > 1;
+    for (idx, path) in dirs.iter().enumerate() {
+        if (multiple || opts.with_header || opts.recursive) && !opts.summary_json && !opts.json && !opts.csv && !opts.stat {
+            writeln!(out, "{}:", header_name(path, opts))?;
         }
-        
-        if multiple && idx + 1 < paths.len() {
-            println!();
+        let fd_listing = match opts.fd_dirs.iter().find(|(fd_path, _)| fd_path == *path) {
+            Some(&(_, fd)) => match FdListing::enter(fd, opts) {
+                Ok(listing) => Some(listing),
+                Err(e) => {
+                    eprintln!("rdir: cannot list fd {}: {}", fd, e);
+                    EXIT_FAILURE.store(true, AtomicOrdering::Relaxed);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let (path, dir_opts) = match &fd_listing {
+            Some(listing) => (Path::new("."), &listing.opts),
+            None => (path.as_path(), opts),
+        };
+        let listed = list_named_dir(path, dir_opts, out);
+        if let Some(listing) = fd_listing {
+            listing.leave()?;
+        }
+        if listed? && multiple && idx + 1 < dirs.len() && !opts.compact {
+            writeln!(out)?;
         }
     }
+    Ok(())
 }
 
-fn print_help() {
-    let help = "rdir: a Rust implementation of directory listing\n\n\
-Usage: rdir [OPTIONS] [PATH]...\n\
-If no PATH is given, the current directory is listed.  Multiple paths\n\
-may be given and will be listed in sequence.\n\n\
-Options:\n\
-  -1                     List one entry per line (disables column view)\n\
-  -a, --all              Do not ignore entries starting with '.'\n\
-  -A, --almost-all       Like -a but excludes '.' and '..' (read_dir already excludes them)\n\
-  -d, --dirs             Show only directories\n\
-  -f, --files            Show only files\n\
-  -l, --long             Use a long listing format (perms, links, uid, gid, size, date)\n\
-  --report              Show a summary of the number of files and folders displayed\n\
-  --tree[=DEPTH]         Recurse into directories and show a tree view.\n\
-                         Omitting DEPTH uses a default of 3.  A DEPTH of 0\n\
-                         or a negative number prints the entire tree.\n\
-  --gs, --git-status     Show git status for each entry (if inside a git repository)\n\
-  --sd, --sort-dirs      Group directories before files (mutually exclusive with --sf)\n\
-  --sf, --sort-files     Group files before directories (mutually exclusive with --sd)\n\
-  -t                     Sort entries by modification time, newest first\n\
-  --light                Use a light colour scheme (for light terminal backgrounds)\n\
-  --dark                 Use the default dark colour scheme (default)\n\
-  --non-human-readable   Print file sizes in bytes rather than a human readable format\n\
-  -h, --help             Print this help message\n\
-  -v, --version          Print the version and exit\n";
-    print!("{}", help);
-    io::stdout().flush().unwrap();
-}
-
-fn print_version() {
-    // Use environment variable set by Cargo
-    println!("rdir version {}", env!("CARGO_PKG_VERSION"));
-}
+/// Lists one directory from the command line, less its header.  Returns
+/// whether a blank line may separate it from the next one.
+fn list_named_dir(path: &Path, opts: &Options, out: &mut dyn Write) -> io::Result<bool> {
+    if opts.stat {
+        print_stat(path, opts, out)?;
+        return Ok(true);
+    }
 
-fn git_statuses(path: &Path) -> HashMap<PathBuf, GitState> {
-    let mut map: HashMap<PathBuf, GitState> = HashMap::new();
-    let output = Command::new("git")
-        .arg("status")
-        .arg("--porcelain")
-        .current_dir(path)
-        .output();
+    let mut counts = Counts::default();
+    let mut progress = Progress::new(opts.progress, opts.progress_always);
     
-    if let Ok(output) = output {
-        if output.status.success() {
-            if let Ok(stdout) = String::from_utf8(output.stdout) {
-                for line in stdout.lines() {
-                    if line.len() < 3 {
-                        continue;
-                    }
-                    let x = line.as_bytes()[0] as char;
-                    let y = line.as_bytes()[1] as char;
-                    let remainder = &line[3..];
-                    let rel_path = if let Some(idx) = remainder.find(" -> ") {
-                        PathBuf::from(&remainder[idx + 4..])
-                    } else {
-                        PathBuf::from(remainder)
-                    };
-                    let state = parse_git_state(x, y);
-                    map.insert(rel_path, state);
-                }
+    if let Some(depth) = opts.tree_depth {
+        let git_map = if opts.git_status || opts.git_ignore {
+            load_git_statuses(path, opts)
+        } else {
+            HashMap::new()
+        };
+        if opts.json {
+            let children = tree_json(path, depth, opts, &git_map, &mut counts, &mut progress);
+            progress.clear();
+            writeln!(out, "[{}]", children.join(","))?;
+        } else {
+            if opts.csv {
+                writeln!(out, "{},depth", CSV_HEADER)?;
             }
+            let mut ancestors = HashSet::new();
+            print_tree(path, path, "".to_string(), depth, opts, &git_map, &mut ancestors, &mut counts, &mut progress, out)?;
         }
+    } else if opts.recursive && !opts.summary_json && !opts.json && !opts.csv && !opts.long0 {
+        list_recursive(path, opts, &mut counts, &mut progress, out)?;
+    } else {
+        list_dir(path, opts, &mut counts, &mut progress, out)?;
     }
-    map
+    progress.clear();
+    
+    if opts.summary_json {
+        print_summary_json(path, &counts, opts, out)?;
+        return Ok(false);
+    }
+    if opts.json || opts.csv {
+        return Ok(false);
+    }
+    
+    if opts.report {
+        print_report(&counts, opts, out)?;
+    }
+    if opts.tree_summary_icons && opts.tree_depth.is_some() {
+        // Without icons the tally falls back to the textual report.
+        if opts.icon_mode != IconMode::Never {
+            print_icon_summary(&counts, opts, out)?;
+        } else if !opts.report {
+            print_report(&counts, opts, out)?;
+        }
+    }
+    Ok(true)
 }
 
-fn parse_git_state(x: char, y: char) -> GitState {
-    let c = if x != ' ' { x } else { y };
-    match c {
-        'A' | 'C' => GitState::Added,
-        'M' => GitState::Modified,
-        'D' => GitState::Deleted,
-        'R' => GitState::Renamed,
-        'T' => GitState::TypeChanged,
-        '?' => GitState::Untracked,
-        '!' => GitState::Ignored,
-        _ => GitState::None,
+/// `--watch`: clears the screen and lists `paths` again each time `wait`
+/// returns true.  The width comes from `width` afresh every round, so a
+/// resized terminal reflows the next listing, unless `--width` fixed it.
+fn watch_paths(
+    paths: &[PathBuf],
+    opts: &Options,
+    out: &mut dyn Write,
+    width: &mut dyn FnMut() -> usize,
+    wait: &mut dyn FnMut() -> bool,
+) -> io::Result<()> {
+    let mut round = opts.clone();
+    loop {
+        if opts.width.is_none() {
+            round.width = Some(width());
+        }
+        write!(out, "\x1b[H\x1b[2J")?;
+        list_paths(paths, &round, out)?;
+        out.flush()?;
+        if !wait() {
+            return Ok(());
+        }
     }
 }
 
-fn perm_string(file_type: &FileType, metadata: &Metadata) -> String {
-    let mut s = String::new();
-    
-    let type_char = if file_type.is_dir() {
-        'd'
+/// Whether a path from the command line is listed as an entry itself rather
+/// than read as a directory.  A link to a directory counts as the directory,
+/// as it does for ls.
+fn names_file(path: &Path) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(md) if md.file_type().is_symlink() => !path.is_dir(),
+        Ok(md) => !md.is_dir(),
+        Err(_) => false,
+    }
+}
+
+/// With `--strict-utf8`, reports a path that could only be shown lossily
+/// and marks the run as failed, so the caller can skip it.
+fn rejects_name(path: &Path, opts: &Options) -> bool {
+    if !opts.strict_utf8 || path.to_str().is_some() {
+        return false;
+    }
+    eprintln!("rdir: file name is not valid UTF-8: {}", escape_non_utf8(path));
+    EXIT_FAILURE.store(true, AtomicOrdering::Relaxed);
+    true
+}
+
+/// Adds one entry to the tally for the summary line and `--summary-json`.
+fn count_entry(counts: &mut Counts, metadata: &fs::Metadata, path: &Path, opts: &Options) {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        counts.dirs += 1;
     } else if file_type.is_symlink() {
-        'l'
+        if opts.broken_links == BrokenLinks::Plain || !is_broken_symlink(path) {
+            counts.symlinks += 1;
+        } else {
+            counts.broken_symlinks += 1;
+        }
     } else {
         #[cfg(unix)]
         {
             use std::os::unix::fs::FileTypeExt;
             if file_type.is_fifo() {
-                'p'
+                counts.pipes += 1;
             } else if file_type.is_socket() {
-                's'
+                counts.sockets += 1;
             } else if file_type.is_block_device() {
-                'b'
+                counts.block_devices += 1;
             } else if file_type.is_char_device() {
-                'c'
+                counts.char_devices += 1;
             } else {
-                '-'
+                counts.files += 1;
+                counts.total_bytes += metadata.len();
             }
         }
         #[cfg(not(unix))]
         {
-            '-'
+            counts.files += 1;
+            counts.total_bytes += metadata.len();
         }
-    };
-    s.push(type_char);
-    
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mode = metadata.permissions().mode();
-        s.push(if mode & 0o400 != 0 { 'r' } else { '-' });
-        s.push(if mode & 0o200 != 0 { 'w' } else { '-' });
-        s.push(if mode & 0o100 != 0 { 'x' } else { '-' });
-        s.push(if mode & 0o40 != 0 { 'r' } else { '-' });
-        s.push(if mode & 0o20 != 0 { 'w' } else { '-' });
-        s.push(if mode & 0o10 != 0 { 'x' } else { '-' });
-        s.push(if mode & 0o4 != 0 { 'r' } else { '-' });
-        s.push(if mode & 0o2 != 0 { 'w' } else { '-' });
-        s.push(if mode & 0o1 != 0 { 'x' } else { '-' });
     }
-    #[cfg(not(unix))]
-    {
-        let _ = metadata;
-        for _ in 0..9 {
-            s.push('-');
+}
+
+/// Lists the non-directories named on the command line as one listing, each
+/// shown by the path it was given as.
+fn list_files(files: &[&PathBuf], opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    if opts.summary_json {
+        for path in files {
+            let mut counts = Counts::default();
+            match entry_metadata(path, opts) {
+                Ok(md) => count_entry(&mut counts, &md, path, opts),
+                Err(e) => {
+                    eprintln!("rdir: cannot access {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+            print_summary_json(path, &counts, opts, out)?;
         }
+        return Ok(());
     }
-    s
+    let mut entries = Vec::new();
+    let mut git_maps: HashMap<PathBuf, GitMap> = HashMap::new();
+    for path in files {
+        let metadata = match entry_metadata(path, opts) {
+            Ok(md) => md,
+            Err(e) => {
+                eprintln!("rdir: cannot access {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let kind = symbols::get_file_kind(&metadata.file_type(), path);
+        let git = if opts.git_status { git_entry_for(path, &mut git_maps, opts) } else { None };
+        let (git_state, git_flags, renamed_from) = match git {
+            Some(git) => (git.state, git.flags, git.renamed_from.clone()),
+            None => (GitState::None, GitFlags::CLEAN, None),
+        };
+        entries.push(EntryInfo {
+            path: path.to_path_buf(),
+            name: path.as_os_str().to_owned(),
+            metadata,
+            icon: icon_for(kind, opts),
+            git_state,
+            git_flags,
+            renamed_from,
+            hidden_links: 0,
+            link_group: None,
+            is_new: false,
+            total_size: None,
+        });
+    }
+    sort_entries(&mut entries, opts, false, false);
+    print_entries(entries, opts, out, false)
 }
 
-fn format_time(st: SystemTime) -> String {
-    let duration = match st.duration_since(UNIX_EPOCH) {
-        Ok(d) => d,
-        Err(e) => e.duration(),
+/// `--list-colors`: one line per scheme field with its name, the name
+/// drawn in that colour, and the raw escape sequence.
+fn print_colors(scheme: &ColorScheme, out: &mut dyn Write) -> io::Result<()> {
+    let name_w = scheme.fields().iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, code) in scheme.fields() {
+        let swatch = format!("{}{}{}", code, name, scheme.reset);
+        writeln!(
+            out,
+            "{}  {}  {}",
+            pad_right(name, name_w),
+            pad_right(&swatch, name_w),
+            code.replace('\x1b', "\\e"),
+        )?;
+    }
+    Ok(())
+}
+
+/// Renders a path with every byte that is not printable ASCII as `\\xNN`,
+/// so names that are not valid UTF-8 can be reported without losing bytes.
+fn escape_non_utf8(path: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
     };
-    let secs = duration.as_secs();
-    let days = secs / 86_400;
-    let mut rem_secs = secs % 86_400;
-    let hour = (rem_secs / 3_600) as u32;
-    rem_secs %= 3_600;
-    let minute = (rem_secs / 60) as u32;
-    
-    let mut year: i32 = 1970;
-    let mut day_count = days as i64;
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
     
-    loop {
-        let leap = is_leap_year(year);
-        let year_days = if leap { 366 } else { 365 };
-        if day_count >= year_days {
-            day_count -= year_days;
-            year += 1;
+    let mut out = String::new();
+    for b in bytes {
+        if b.is_ascii_graphic() || b == b' ' {
+            out.push(b as char);
         } else {
-            break;
+            out.push_str(&format!("\\x{:02x}", b));
         }
     }
-    
-    let month_lengths = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-    let month_lengths_leap = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-    let months = if is_leap_year(year) { &month_lengths_leap } else { &month_lengths };
-    let mut month: usize = 0;
-    while day_count >= months[month] as i64 {
-        day_count -= months[month] as i64;
-        month += 1;
+    out
+}
+
+/// Expands the few backslash escapes accepted in string-valued flags.
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
     }
-    let day = day_count + 1;
-    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month + 1, day, hour, minute)
+    out
 }
 
-fn is_leap_year(year: i32) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+fn read_files0_from(source: &str) -> io::Result<Vec<PathBuf>> {
+    use std::io::Read;
+    let mut data = Vec::new();
+    if source == "-" {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        fs::File::open(source)?.read_to_end(&mut data)?;
+    }
+    Ok(split_paths(&data, 0))
 }
 
-fn format_size(size: u64, human_readable: bool) -> String {
-    if !human_readable {
-        return size.to_string();
+/// Splits raw bytes into paths at every `sep`, skipping empty chunks.
+fn split_paths(data: &[u8], sep: u8) -> Vec<PathBuf> {
+    let mut list = Vec::new();
+    for chunk in data.split(|&b| b == sep) {
+        if chunk.is_empty() {
+            continue;
+        }
+        list.push(path_from_bytes(chunk));
     }
-    let units = ["B", "K", "M", "G", "T", "P", "E", "Z", "Y"];
-    let mut s = size as f64;
-    let mut idx = 0;
-    while s >= 1024.0 && idx < units.len() - 1 {
-        s /= 1024.0;
-        idx += 1;
+    list
+}
+
+/// A path from raw bytes, lossily outside unix.
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
     }
-    if idx == 0 {
-        format!("{}{}", size, units[idx])
-    } else {
-        format!("{:.1}{}", s, units[idx])
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
     }
 }
 
-fn visible_len(s: &str) -> usize {
-    let bytes = s.as_bytes();
-    let mut i = 0;
-    let mut len = 0;
-    let mut in_escape = false;
-    while i < bytes.len() {
-        let b = bytes[i];
-        if in_escape {
-            if b == b'm' {
-                in_escape = false;
-            }
-        } else {
-            if b == 0x1b {
-                if i + 1 < bytes.len() && bytes[i + 1] == b'[' {
-                    in_escape = true;
-                    i += 1;
-                } else {
-                    len += 1;
-                }
-            } else {
-                len += 1;
-            }
-        }
-        i += 1;
+/// The directory holding `path`: `.` for a bare name.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
     }
-    len
 }
 
-fn list_dir(path: &Path, opts: &Options, counts: &mut Counts) {
-    let read_dir = match fs::read_dir(path) {
-        Ok(rd) => rd,
-        Err(e) => {
-            eprintln!("rdir: cannot access {}: {}", path.display(), e);
-            return;
+/// Git status of the single path `path`, loading the statuses for each
+/// directory into `git_maps` the first time one of its entries is asked about.
+fn git_entry_for<'a>(path: &Path, git_maps: &'a mut HashMap<PathBuf, GitMap>, opts: &Options) -> Option<&'a GitEntry> {
+    let parent = parent_dir(path);
+    let key = fs::canonicalize(&parent).ok()?.join(path.file_name()?);
+    git_maps.entry(parent).or_insert_with_key(|parent| load_git_statuses(parent, opts)).get(&key)
+}
+
+/// `--stdin-names`: renders every newline-separated path on stdin as an
+/// entry of its own, one per line, whatever directory it lives in.
+fn print_stdin_names(opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    use std::io::Read;
+    let mut data = Vec::new();
+    io::stdin()
+        .read_to_end(&mut data)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read stdin: {}", e)))?;
+    let mut git_maps: HashMap<PathBuf, GitMap> = HashMap::new();
+    for path in split_paths(&data, b'\n') {
+        if rejects_name(&path, opts) {
+            continue;
         }
-    };
-    
-    let git_map = if opts.git_status {
-        git_statuses(path)
-    } else {
-        HashMap::new()
-    };
-    
-    let mut entries: Vec<EntryInfo> = Vec::new();
-    for res in read_dir {
-        match res {
-            Ok(entry) => {
-                let file_name = entry.file_name();
-                let file_name_str = file_name.to_string_lossy();
-                
-                if !opts.all {
-                    if file_name_str.starts_with('.') {
-                        continue;
-                    }
-                }
-                
-                let metadata = match fs::symlink_metadata(entry.path()) {
-                    Ok(md) => md,
-                    Err(_) => continue,
-                };
-                
-                let file_type = metadata.file_type();
-                
-                if opts.dirs_only && !file_type.is_dir() {
-                    continue;
-                }
-                if opts.files_only && file_type.is_dir() {
-                    continue;
-                }
-                
-                let rel_path = match entry.path().strip_prefix(path) {
-                    Ok(p) => p.to_owned(),
-                    Err(_) => entry.path(),
-                };
-                let git_state = git_map.get(&rel_path).cloned().unwrap_or(GitState::None);
-                let icon = symbols::get_file_icon(&file_type, &entry.path());
-                
-                if file_type.is_dir() {
-                    counts.dirs += 1;
-                } else if file_type.is_symlink() {
-                    if fs::read_link(entry.path()).map_or(true, |tgt| tgt.exists()) {
-                        counts.symlinks += 1;
-                    } else {
-                        counts.broken_symlinks += 1;
-                    }
-                } else {
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::FileTypeExt;
-                        if file_type.is_fifo() {
-                            counts.pipes += 1;
-                        } else if file_type.is_socket() {
-                            counts.sockets += 1;
-                        } else if file_type.is_block_device() {
-                            counts.block_devices += 1;
-                        } else if file_type.is_char_device() {
-                            counts.char_devices += 1;
-                        } else {
-                            counts.files += 1;
-                        }
-                    }
-                    #[cfg(not(unix))]
-                    {
-                        counts.files += 1;
-                    }
-                }
-                
-                entries.push(EntryInfo {
-                    entry,
-                    metadata,
-                    icon,
-                    git_state,
-                });
-            }
+        let metadata = match entry_metadata(&path, opts) {
+            Ok(md) => md,
             Err(e) => {
-                eprintln!("rdir: error reading directory: {}", e);
+                eprintln!("rdir: cannot access {}: {}", path.display(), e);
+                EXIT_FAILURE.store(true, AtomicOrdering::Relaxed);
+                continue;
             }
-        }
+        };
+        let file_type = metadata.file_type();
+        let (git_state, git_flags) = if opts.git_status {
+            git_entry_for(&path, &mut git_maps, opts).map_or((GitState::None, GitFlags::CLEAN), |git| (git.state, git.flags))
+        } else {
+            (GitState::None, GitFlags::CLEAN)
+        };
+        // Show the path as given rather than just its last component.
+        let info = EntryInfo {
+            icon: icon_for(symbols::get_file_kind(&file_type, &path), opts),
+            name: path.clone().into_os_string(),
+            path,
+            metadata,
+            git_state,
+            git_flags,
+            renamed_from: None,
+            hidden_links: 0,
+            link_group: None,
+            is_new: false,
+            total_size: None,
+        };
+        writeln!(out, "{}", build_short_display(&info, opts))?;
     }
-    
-    entries.sort_by(|a, b| {
-        let a_dir = a.metadata.file_type().is_dir();
-        let b_dir = b.metadata.file_type().is_dir();
-        
-        if opts.sort_dirs_first && a_dir != b_dir {
-            if a_dir { return std::cmp::Ordering::Less; }
-            else { return std::cmp::Ordering::Greater; }
+    Ok(())
+}
+
+/// Checks that an inherited fd is open on a directory.
+#[cfg(unix)]
+fn check_fd_dir(fd: u32) -> io::Result<()> {
+    let fd = libc::c_int::try_from(fd).map_err(|_| io::Error::from_raw_os_error(libc::EBADF))?;
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(io::Error::other("not a directory"));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_fd_dir(_fd: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--fd is only supported on unix"))
+}
+
+/// A `--fd` directory being listed.  The working directory is moved onto
+/// the descriptor with `fchdir` from `enter` until `leave`, so the listing is
+/// read as `.` and every lookup in it, down to subdirectories and link
+/// targets, is anchored to the descriptor rather than to any path.  `/dev/fd`
+/// entries only act as directories on Linux, so they cannot be used for this.
+struct FdListing {
+    #[cfg(unix)]
+    previous: fs::File,
+    /// The options with the `--fd` directory renamed to `.` for its headers.
+    opts: Options,
+}
+
+impl FdListing {
+    #[cfg(unix)]
+    fn enter(fd: u32, opts: &Options) -> io::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Only ever handed to fchdir, so it needs no read permission where
+        // O_PATH exists: an unreadable working directory is fine.
+        let mut options = fs::OpenOptions::new();
+        options.read(true);
+        #[cfg(target_os = "linux")]
+        options.custom_flags(libc::O_PATH | libc::O_DIRECTORY);
+        #[cfg(not(target_os = "linux"))]
+        options.custom_flags(libc::O_DIRECTORY);
+        let previous = options.open(".")?;
+        if unsafe { libc::fchdir(fd as libc::c_int) } != 0 {
+            return Err(io::Error::last_os_error());
         }
-        if opts.sort_files_first && a_dir != b_dir {
-            if a_dir { return std::cmp::Ordering::Greater; }
-            else { return std::cmp::Ordering::Less; }
+        let mut opts = opts.clone();
+        opts.fd_dirs = vec![(PathBuf::from("."), fd)];
+        Ok(Self { previous, opts })
+    }
+
+    #[cfg(not(unix))]
+    fn enter(_fd: u32, _opts: &Options) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--fd is only supported on unix"))
+    }
+
+    /// Moves back to the working directory from before `enter`.  Failing that
+    /// stops the run, since relative paths after it would be looked up in the
+    /// wrong place.
+    #[cfg(unix)]
+    fn leave(self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        if unsafe { libc::fchdir(self.previous.as_raw_fd()) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("cannot return to the working directory: {}", e)));
         }
-        if opts.sort_time {
-            let a_time = a.metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-            let b_time = b.metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-            match b_time.cmp(&a_time) {
-                std::cmp::Ordering::Equal => {}
-                ord => return ord,
-            }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn leave(self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How a directory is named in its `PATH:` header.  Directories reached
+/// through `--fd=N` show as `fd N` rather than the `/dev/fd` path behind it.
+fn header_name(path: &Path, opts: &Options) -> String {
+    for (fd_path, fd) in &opts.fd_dirs {
+        if let Ok(rest) = path.strip_prefix(fd_path) {
+            let label = PathBuf::from(format!("fd {}", fd));
+            let label = if rest.as_os_str().is_empty() { label } else { label.join(rest) };
+            return display_path(&label, opts);
         }
-        
-        let a_name = a.entry.file_name().to_string_lossy().to_lowercase();
-        let b_name = b.entry.file_name().to_string_lossy().to_lowercase();
-        a_name.cmp(&b_name)
-    });
-    
-    if opts.long {
-        let mut link_w = 0;
-        let mut uid_w = 0;
-        let mut gid_w = 0;
-        let mut size_w = 0;
-        
-        for info in &entries {
+    }
+    display_path(path, opts)
+}
+
+/// Checks the invariants the column layout relies on, measuring with
+/// `width`, and reports each one on stderr.  Not listed in --help; it exists
+/// for bug reports.
+fn self_test(width: fn(&str) -> usize) -> bool {
+    let mut ok = true;
+    let mut check = |name: &str, passed: bool| {
+        eprintln!("self-test: {} ... {}", name, if passed { "ok" } else { "FAILED" });
+        ok &= passed;
+    };
+
+    let scheme = ColorScheme::dark();
+    let samples = [
+        ("", 0),
+        ("a", 1),
+        ("main.rs", 7),
+        ("name with spaces", 16),
+        ("\u{1f4c1} src", 6),
+        ("na\u{ef}ve", 5),
+        ("\u{6f22}\u{5b57}", 4),
+        ("e\u{301}", 1),
+        ("\u{2699}\u{fe0f}", 2),
+    ];
+    check(
+        "names take the columns a terminal draws them in",
+        samples.iter().all(|&(plain, columns)| width(plain) == columns),
+    );
+    check(
+        "colour codes do not count towards width",
+        samples.iter().all(|&(plain, _)| {
+            let colored = format!("{}{}{}", scheme.dir, plain, scheme.reset);
+            width(&colored) == width(plain)
+        }),
+    );
+    check(
+        "padding reaches the column width",
+        samples.iter().all(|&(plain, columns)| {
+            let colored = format!("{}{}{}", scheme.file, plain, scheme.reset);
+            width(&pad_left(&colored, columns + 3)) == columns + 3
+                && width(&pad_right(&colored, columns + 3)) == columns + 3
+        }),
+    );
+    check(
+        "grid columns are sized by their own entries",
+        grid_layout(&[30, 2, 2, 2], 40, false) == (2, vec![30, 2])
+            && grid_layout(&[30, 2, 2, 2], 40, true) == (2, vec![30, 2, 2])
+            && grid_layout(&[4, 4, 4, 4], 40, false) == (1, vec![4, 4, 4, 4])
+            && grid_layout(&[50], 40, false) == (1, vec![50]),
+    );
+    check(
+        "grid covers every entry exactly once, down or across",
+        (0_usize..64).all(|n| {
+            (1..10).all(|cols| {
+                [false, true].iter().all(|&across| {
+                    let rows = n.div_ceil(cols);
+                    let mut seen = vec![0; n];
+                    for r in 0..rows {
+                        for c in 0..cols {
+                            let idx = grid_index(r, c, rows, cols, across);
+                            if idx < n {
+                                seen[idx] += 1;
+                            }
+                        }
+                    }
+                    seen.iter().all(|&times| times == 1)
+                })
+            })
+        }),
+    );
+    ok
+}
+
+fn print_help() {
+    let help = "rdir: a Rust implementation of directory listing\n\n\
+Usage: rdir [OPTIONS] [PATH]...\n\
+If no PATH is given, the current directory is listed.  Multiple paths\n\
+may be given and will be listed in sequence.\n\n\
+Options:\n\
+  -1                     List one entry per line (disables column view)\n\
+  -x, --across           Fill the columns across each row rather than down\n\
+  --width=N              Lay the grid out for N columns of text instead of the\n\
+                         terminal's width or $COLUMNS; 0 means one entry per line\n\
+  --stream               With -1, print entries as the directory is read instead\n\
+                         of sorting them first, so huge directories start showing\n\
+                         at once.  Ignores -r and --hard-links.  -1 --sort=none\n\
+                         streams too\n\
+  --page=N               Pause after every N entries of a listing until Enter is\n\
+                         pressed (only when stdout is a terminal).  In a grid,\n\
+                         each page is laid out on its own\n\
+  --watch[=INTERVAL]     Clear the screen and list again every INTERVAL (2s by\n\
+                         default; takes the same units as --new-within) until\n\
+                         interrupted.  The grid follows the terminal's width as\n\
+                         it is resized\n\
+  --entry-terminator=STR Append STR to every entry with -1, including the last.\n\
+                         Understands the escapes \\t, \\n, \\0 and \\\\\n\
+  -a, --all              Do not ignore entries starting with '.'\n\
+  -A, --almost-all       Like -a but excludes '.' and '..' (read_dir already excludes them)\n\
+  --strict-utf8          Skip and report entries whose names are not valid UTF-8,\n\
+                         exiting with status 1, instead of showing a lossy name\n\
+  --dedup                Show only the first name seen for each hard-linked file\n\
+                         in a directory, noting how many others were hidden\n\
+  --hard-links           Tag names that are hard links of each other with a shared\n\
+                         number, e.g. '[link 1]'.  Only links within the same\n\
+                         directory are found\n\
+  -d, --dirs             Show only directories\n\
+  -f, --files            Show only files\n\
+  -l, --long             Use a long listing format (perms, links, owner, group, size,\n\
+                         date).  A '@' after the permissions marks extended\n\
+                         attributes and a '+' an ACL\n\
+  -n, --numeric-uid-gid  Like -l, but show the numeric uid and gid\n\
+  --octal, --octal-permissions\n\
+                         Show the mode as a number such as 0755 (including the\n\
+                         setuid, setgid and sticky bits) before the permissions\n\
+  -L, --dereference      Show the type, size and permissions of what symlinks point\n\
+                         at.  Broken links are still shown as links\n\
+  --broken-links=HOW     Show broken symlinks with their own colour ('highlight',\n\
+                         the default), like other symlinks ('plain'), or 'hide' them\n\
+  --blame                Add a column to the long format with the author of the\n\
+                         last commit touching each entry ('-' if untracked)\n\
+  --show-device          Add a column to the long format with the device number\n\
+                         each entry lives on\n\
+  --resolve-mounts       Like --show-device, but show the mount point instead\n\
+                         (Linux only; elsewhere the device number)\n\
+  --blocks               Start each long-format line with the disk space the entry\n\
+                         takes, and give the total as a size (ignored on non-unix\n\
+                         platforms)\n\
+  --block-size=N         Count the 'total' line of a long listing in blocks of N\n\
+                         bytes rather than 1024, e.g. 512 as POSIX ls does\n\
+  -i, --inode            Start each entry with its inode number ('-' on non-unix\n\
+                         platforms)\n\
+  --new-within=AGE       Mark files first committed to git less than AGE ago\n\
+                         with a 'new' badge, e.g. 90m, 12h, 7d or 2w (bare\n\
+                         numbers are seconds).  Runs git log per directory\n\
+  --long0                Print the long format fields separated by NUL bytes, one\n\
+                         record per line: perms, links, uid, gid, size in bytes,\n\
+                         mtime in epoch seconds, git status, name.  No colour\n\
+  --perm=MODE            Show only entries whose permissions match the octal MODE,\n\
+                         as in find(1): '/MODE' any bit set, '-MODE' all bits set,\n\
+                         'MODE' exact match.  Ignored on non-unix platforms\n\
+  --owner=USER           Show only entries owned by USER, a name or a uid\n\
+  --group=GROUP          Show only entries whose group is GROUP, a name or a gid.\n\
+                         Both are ignored on non-unix platforms\n\
+  --report              Show a summary of the number of files and folders displayed\n\
+                         and the total size of the files\n\
+  --tree-summary-icons   After a --tree, print a tally such as '📁 12  📄 45'\n\
+                         using the entry icons (or the --ascii-icons tags)\n\
+  --depth-colors         Colour --tree connectors by level so siblings share a hue\n\
+  --stat                 Print a detailed report for each PATH itself instead of\n\
+                         listing its contents.  With --gs it includes the git state\n\
+  --summary-json         Print only the summary counts as a JSON object per path,\n\
+                         with 'total_bytes' summing the regular files' sizes\n\
+  --json                 Print the entries as a JSON array per path, without\n\
+                         colour.  With --tree, directories carry their entries\n\
+                         in a 'children' array\n\
+  --csv                  Print the entries as CSV rows under a header of\n\
+                         name,type,size,mtime,permissions,git.  With --tree the\n\
+                         name is the path relative to PATH and a depth column\n\
+                         is added\n\
+  --compact              Do not print blank lines between paths or before the report\n\
+  --with-header          Print the 'PATH:' header even when only one path is listed\n\
+  -R, --recursive        List every directory below PATH as well, each under its\n\
+                         own 'PATH:' header.  Symlinked directories are not\n\
+                         followed unless -L is given\n\
+  --tree[=DEPTH]         Recurse into directories and show a tree view.\n\
+                         Omitting DEPTH uses a default of 3.  A DEPTH of 0\n\
+                         or a negative number prints the entire tree.\n\
+  --depth=N              Same as --tree=N\n\
+  --max-entries-per-dir=N\n\
+                         In a tree, show only the first N entries of each\n\
+                         directory and a '… (M more)' line for the rest.\n\
+                         --report still counts everything\n\
+  --flatten[=DEPTH]      Walk the tree like --tree, but print each entry as\n\
+                         its path relative to PATH, one per line and without\n\
+                         icons or colour.  Without DEPTH the walk is unlimited\n\
+  --gs, --git-status     Show git status for each entry (if inside a git repository)\n\
+  --git-ignore           Hide entries matched by the repository's .gitignore rules\n\
+  --git-position=WHERE   Put the git status letter 'leading' the entry (default)\n\
+                         or 'trailing' after its name\n\
+  --git-columns          With --gs, show two letters like git status --short: what\n\
+                         is staged, then what is changed but not staged\n\
+  --name-color=WHICH     With --gs, colour names by file 'type' (default), by\n\
+                         'git' state whenever there is one, or by git state only\n\
+                         for modified and conflicted entries ('git-over-modified')\n\
+  --git-cache            Reuse git status results from a run in the last 2 seconds\n\
+                         as long as HEAD and the index are unchanged.  Results\n\
+                         are kept in $XDG_CACHE_HOME/rdir (or ~/.cache/rdir)\n\
+  --git-shell-out        Always run git status for --gs.  By default the status is\n\
+                         worked out from the repository's index and .gitignore\n\
+                         files, and git is only run when something is staged, a\n\
+                         merge is in progress or the index cannot be read\n\
+  --no-git-shell-out     Work out --gs from the index where possible (default)\n\
+  --sd, --sort-dirs      Group directories before files (mutually exclusive with --sf).\n\
+                         Applies to every level of --tree as well\n\
+  --sf, --sort-files     Group files before directories (mutually exclusive with --sd).\n\
+                         Also --group-directories-last; applies to --tree too\n\
+  -t                     Sort entries by modification time, newest first.  In a\n\
+                         --tree, files and directories mix unless --sd/--sf\n\
+  -S                     Sort entries by size, largest first.  Of -t, -S and\n\
+                         --sort the last one given wins\n\
+  --sort=WORD            Sort by 'name' (default), 'time' (like -t), 'size'\n\
+                         (like -S), 'extension', 'version' (img2 before img10),\n\
+                         'length' of the name (shortest first), 'git' state\n\
+                         (conflicted, modified, added, untracked, clean; implies\n\
+                         --gs), or 'none' to keep the filesystem's order\n\
+  -U                     Same as --sort=none: no sorting and no --sd/--sf\n\
+                         grouping.  The order depends on the filesystem\n\
+  -r, --reverse          Reverse the final order, including any --sd/--sf grouping,\n\
+                         so -tr lists oldest first.  Also applies at each --tree level\n\
+  --group-reverse        Reverse the order inside the directory and file groups\n\
+                         while keeping the groups themselves in place\n\
+  --dotfiles=WHERE       Place hidden entries 'first' (default), 'last', or\n\
+                         'mixed' in with the rest by their name without the dot\n\
+  --light                Use a light colour scheme (for light terminal backgrounds)\n\
+  --dark                 Use the default dark colour scheme (default)\n\
+  --color=WHEN           Colour the output 'always', 'never', or 'auto' (default):\n\
+                         only when stdout is a terminal and NO_COLOR is unset.\n\
+                         'always' keeps colour through pipes (e.g. into less -R)\n\
+                         and overrides NO_COLOR\n\
+  --non-human-readable   Print file sizes in bytes rather than a human readable format\n\
+  --si                   Use powers of 1000 for human readable sizes (KB, MB, GB)\n\
+                         instead of 1024 (K, M, G)\n\
+  --total-size, --du     Show directories with the summed size of every regular\n\
+                         file below them instead of their own size.  Walks each\n\
+                         directory in full, so it can be slow\n\
+  --utc                  Show times in UTC instead of the local timezone\n\
+  --time=WORD            Show and sort (-t) by another timestamp: 'mod' (default),\n\
+                         'access', 'change' or 'status', 'birth' or 'creation'.\n\
+                         Entries without it use their modification time\n\
+  --time-style=STYLE     Show times as 'default' (2024-05-01 13:37), 'iso'\n\
+                         (2024-05-01T13:37:00+02:00) or 'relative' to now,\n\
+                         in the largest whole unit (45s, 2h, 3d, 5mo, 1y).\n\
+                         GNU's 'long-iso' and 'full-iso' name the first two\n\
+  --uniform-size-unit    Show every size in a long listing in the same unit, picked\n\
+                         to fit the largest entry\n\
+  --bytes-aligned        Give long-format size units their own column so the numbers\n\
+                         and units each line up\n\
+  --colorful-columns     Colour the link count and size columns in long format,\n\
+                         and the -i inode column\n\
+  -F, --classify         Append an indicator to names: '/' for directories, '*' for\n\
+                         executables, '@' for symlinks, '|' for FIFOs and '=' for\n\
+                         sockets\n\
+  --classify=mime        Colour regular files by the category of their MIME type\n\
+                         (text, image, audio, video, application)\n\
+  --smart-dir-icons      Give directories the icon of the file type that makes up\n\
+                         most of their contents (peeks at up to 50 entries each)\n\
+  --icon-set=SET         Draw icons as 'emoji' (default), 'nerd' for Nerd Font\n\
+                         glyphs, or 'ascii' tags\n\
+  --ascii-icons          Use short ASCII tags such as [d] and [rs] instead of emoji\n\
+  --ascii                Print nothing but ASCII: implies --ascii-icons, draws the\n\
+                         tree with |-- and `--, and escapes other bytes in names\n\
+                         as \\xNN (edge spaces become \\x20)\n\
+  --icons=WHEN           Show icons 'always' (default), 'never', or 'auto': only\n\
+                         when stdout is a terminal.  --no-icons is 'never'\n\
+  --highlight=TEXT       Highlight every occurrence of TEXT in entry names.  May be\n\
+                         given more than once\n\
+  --match=GLOB           Only show entries whose name matches GLOB ('*', '?' and\n\
+                         '[a-z]' sets).  May be repeated; in a --tree,\n\
+                         directories are always shown so they can be entered\n\
+  --ignore=GLOB          Hide entries whose name matches GLOB, even if a --match\n\
+                         pattern also matches.  May be repeated\n\
+  --resolve-links        Show symlink targets as absolute, normalised paths\n\
+  --no-mark-spaces       Print leading and trailing spaces in names as they are\n\
+                         instead of as '␣'\n\
+  --progress             Show a running count of scanned entries on stderr while\n\
+                         walking slow directories (only when stderr is a terminal)\n\
+  --progress=WHEN        Show it 'auto' (as --progress does), 'always' (also when\n\
+                         stderr is not a terminal) or 'never'\n\
+  --threads=N            Read file metadata in large directories on N threads\n\
+                         (default: one per CPU; 1 turns this off)\n\
+  --files0-from=FILE     Read NUL-separated paths to list from FILE ('-' for stdin)\n\
+  --stdin-names          Render each newline-separated path read from stdin as an\n\
+                         entry of its own, one per line, and ignore PATH\n\
+  --fd=N                 List the directory open on file descriptor N (unix only)\n\
+  --list-colors          Print every colour of the selected scheme with a sample\n\
+                         and its escape sequence, then exit\n\
+  -h, --help             Print this help message\n\
+  -v, --version          Print the version and exit\n";
+    print!("{}", help);
+    io::stdout().flush().unwrap();
+}
+
+fn print_version() {
+    // Use environment variable set by Cargo
+    println!("rdir version {}", env!("CARGO_PKG_VERSION"));
+}
+
+const GIT_CACHE_TTL: Duration = Duration::from_secs(2);
+
+fn load_git_statuses(path: &Path, opts: &Options) -> GitMap {
+    if opts.git_direct {
+        if let Some(map) = git_statuses_direct(path, opts) {
+            return map;
+        }
+    }
+    if !opts.git_cache {
+        return git_statuses(path, opts.git_ignore);
+    }
+    let key = match git_cache_key(path, opts.git_ignore) {
+        Some(key) => key,
+        None => return git_statuses(path, opts.git_ignore),
+    };
+    let cache_file = match git_cache_file(&key) {
+        Some(file) => file,
+        None => return git_statuses(path, opts.git_ignore),
+    };
+    if let Some(map) = read_git_cache(&cache_file, &key) {
+        return map;
+    }
+    let map = git_statuses(path, opts.git_ignore);
+    // git status refreshes the index when it finds stale stat data, which
+    // changes the key, so file the result under the key as it is now.
+    if let Some(key) = git_cache_key(path, opts.git_ignore) {
+        if let Some(cache_file) = git_cache_file(&key) {
+            write_git_cache(&cache_file, &key, &map);
+        }
+    }
+    map
+}
+
+/// Identifies a git status result: the listed directory, what HEAD points
+/// at, when the index was last written, and whether ignored paths were
+/// asked for.  Any commit, checkout or stage changes the key and so
+/// invalidates the cache.
+fn git_cache_key(path: &Path, ignored: bool) -> Option<String> {
+    let dir = fs::canonicalize(path).ok()?;
+    let (_, git_dir) = find_git_dir(&dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let head_target = match head.strip_prefix("ref: ") {
+        Some(reference) => fs::read_to_string(git_dir.join(reference)).unwrap_or_default(),
+        None => String::new(),
+    };
+    let index_mtime = fs::metadata(git_dir.join("index"))
+        .and_then(|md| md.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    Some(format!("{}\t{}\t{}\t{}\t{}", dir.display(), head, head_target.trim(), index_mtime, ignored))
+}
+
+/// The top of the working tree containing `dir` and its git directory,
+/// found by looking for `.git` in `dir` and each of its ancestors.
+fn find_git_dir(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in dir.ancestors() {
+        let candidate = ancestor.join(".git");
+        if candidate.is_dir() {
+            return Some((ancestor.to_path_buf(), candidate));
+        }
+        if candidate.is_file() {
+            let contents = fs::read_to_string(&candidate).ok()?;
+            let target = contents.trim().strip_prefix("gitdir: ")?;
+            return Some((ancestor.to_path_buf(), ancestor.join(target)));
+        }
+    }
+    None
+}
+
+/// `git status` without running git, which `--gs` tries first: index
+/// entries are checked against the working tree by their recorded size,
+/// mode and mtime, and `path` is walked as deep as the listing goes for
+/// files the index does not know.  Only an index that matches the HEAD tree
+/// is understood, so anything staged, a conflicted merge and `--git-columns`
+/// still ask git.  `None` whenever git has to be asked after all.
+fn git_statuses_direct(path: &Path, opts: &Options) -> Option<GitMap> {
+    if opts.git_columns {
+        return None;
+    }
+    let dir = fs::canonicalize(path).ok()?;
+    let (top, git_dir) = find_git_dir(&dir)?;
+    let index = git_index::read(&git_dir)?;
+    if index.tree.is_none() || index.tree != git_object::head_tree(&git_dir) {
+        return None;
+    }
+    let index_mtime = fs::metadata(git_dir.join("index")).and_then(|md| md.modified()).ok()?;
+
+    let mut map: GitMap = HashMap::new();
+    // Every tracked path and every directory holding one.
+    let mut tracked: HashSet<PathBuf> = HashSet::new();
+    for entry in &index.entries {
+        tracked.insert(entry.path.clone());
+        for ancestor in entry.path.ancestors().skip(1) {
+            if !tracked.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+        if entry.stage != 0 {
+            return None;
+        }
+        if entry.mode == git_index::GITLINK_MODE {
+            continue;
+        }
+        let state = worktree_state(&top.join(&entry.path), entry, index_mtime)?;
+        if state == GitState::None {
+            continue;
+        }
+        let flags = GitFlags { index: GitState::None, worktree: state };
+        map.insert(top.join(&entry.path), GitEntry { state, flags, renamed_from: None });
+    }
+
+    let mut rules = IgnoreRules::new(&top, &git_dir);
+    let rel = dir.strip_prefix(&top).ok()?;
+    // Inside an ignored directory nothing is untracked.
+    let inside_ignored = rel.ancestors().any(|a| {
+        !a.as_os_str().is_empty() && !tracked.contains(a) && rules.is_ignored(a, true)
+    });
+    if !inside_ignored {
+        let depth = opts.tree_depth.unwrap_or(1);
+        walk_untracked(&top, &dir, depth, &tracked, &mut rules, opts.git_ignore, &mut map);
+    }
+    Some(map)
+}
+
+/// How the working tree copy of an index entry differs from what was
+/// staged, judging by the stat data git recorded.  A file touched without
+/// being changed counts as modified until git refreshes the index.  `None`
+/// when only the contents could tell: the file was written no earlier than
+/// the index, or git cleared the recorded size for that reason.
+fn worktree_state(path: &Path, entry: &git_index::Entry, index_mtime: SystemTime) -> Option<GitState> {
+    let md = match fs::symlink_metadata(path) {
+        Ok(md) => md,
+        Err(_) => return Some(GitState::Deleted),
+    };
+    let was_link = entry.mode & 0o170000 == 0o120000;
+    if md.file_type().is_symlink() != was_link || md.is_dir() {
+        return Some(GitState::TypeChanged);
+    }
+    let recorded = UNIX_EPOCH + Duration::new(entry.mtime_secs.into(), entry.mtime_nsecs);
+    if recorded >= index_mtime || (entry.size == 0 && md.len() != 0) {
+        return None;
+    }
+    #[cfg(unix)]
+    let changed = {
+        use std::os::unix::fs::MetadataExt;
+        md.size() as u32 != entry.size
+            || md.mtime() as u32 != entry.mtime_secs
+            || (entry.mtime_nsecs != 0 && md.mtime_nsec() as u32 != entry.mtime_nsecs)
+            || (!was_link && md.mode() & 0o100 != entry.mode & 0o100)
+    };
+    #[cfg(not(unix))]
+    let changed = {
+        let secs = md.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        md.len() as u32 != entry.size || secs as u32 != entry.mtime_secs
+    };
+    if changed {
+        Some(GitState::Modified)
+    } else {
+        Some(GitState::None)
+    }
+}
+
+/// Marks what `dir` holds that is neither tracked nor ignored, and with
+/// `ignored` what is ignored too, going into tracked subdirectories while
+/// `depth` allows.  Like `git status`, an untracked or ignored directory is
+/// reported once rather than its contents.
+fn walk_untracked(top: &Path, dir: &Path, depth: usize, tracked: &HashSet<PathBuf>, rules: &mut IgnoreRules, ignored: bool, map: &mut GitMap) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(_) => return,
+    };
+    for entry in read_dir.flatten() {
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        let rel = path.strip_prefix(top).unwrap_or(&path).to_path_buf();
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if tracked.contains(&rel) {
+            if is_dir && depth > 1 {
+                walk_untracked(top, &path, depth - 1, tracked, rules, ignored, map);
+            }
+            continue;
+        }
+        let state = if rules.is_ignored(&rel, is_dir) {
+            if !ignored {
+                continue;
+            }
+            GitState::Ignored
+        } else {
+            GitState::Untracked
+        };
+        if ignored && state == GitState::Untracked && is_dir && depth > 1 {
+            walk_ignored(top, &path, depth - 1, rules, map);
+        }
+        let flags = GitFlags { index: state, worktree: state };
+        map.insert(path, GitEntry { state, flags, renamed_from: None });
+    }
+}
+
+/// Marks what is ignored inside the untracked directory `dir`, which
+/// `git status --ignored` reports even though it lists the untracked
+/// directory itself only once.
+fn walk_ignored(top: &Path, dir: &Path, depth: usize, rules: &mut IgnoreRules, map: &mut GitMap) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(_) => return,
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        let rel = path.strip_prefix(top).unwrap_or(&path).to_path_buf();
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if rules.is_ignored(&rel, is_dir) {
+            let flags = GitFlags { index: GitState::Ignored, worktree: GitState::Ignored };
+            map.insert(path, GitEntry { state: GitState::Ignored, flags, renamed_from: None });
+        } else if is_dir && depth > 1 {
+            walk_ignored(top, &path, depth - 1, rules, map);
+        }
+    }
+}
+
+/// One line of a `.gitignore` (or `info/exclude`) file.
+struct IgnoreRule {
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    /// Matched against the path below the file's directory rather than
+    /// against the name alone.
+    anchored: bool,
+}
+
+/// The ignore rules of a working tree, read from each directory's
+/// `.gitignore` the first time it is needed.
+struct IgnoreRules {
+    top: PathBuf,
+    /// Rules per directory relative to `top`; `info/exclude` goes with the
+    /// top, before its `.gitignore`.
+    by_dir: HashMap<PathBuf, Vec<IgnoreRule>>,
+}
+
+impl IgnoreRules {
+    fn new(top: &Path, git_dir: &Path) -> Self {
+        let mut root = parse_ignore_file(&git_dir.join("info").join("exclude"));
+        root.extend(parse_ignore_file(&top.join(".gitignore")));
+        let mut by_dir = HashMap::new();
+        by_dir.insert(PathBuf::new(), root);
+        IgnoreRules { top: top.to_path_buf(), by_dir }
+    }
+
+    /// Whether `rel`, a path below the top of the working tree, is ignored.
+    /// Rules from deeper directories and later lines win, and a `!` rule
+    /// brings a path back.  Parents are not checked.
+    fn is_ignored(&mut self, rel: &Path, is_dir: bool) -> bool {
+        let name = match rel.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return false,
+        };
+        let mut bases: Vec<PathBuf> = rel.ancestors().skip(1).map(Path::to_path_buf).collect();
+        bases.reverse();
+        let mut ignored = false;
+        for base in bases {
+            let top = &self.top;
+            let rules = self
+                .by_dir
+                .entry(base.clone())
+                .or_insert_with(|| parse_ignore_file(&top.join(&base).join(".gitignore")));
+            let below = rel.strip_prefix(&base).unwrap_or(rel).to_string_lossy().into_owned();
+            for rule in rules.iter() {
+                if rule.dir_only && !is_dir {
+                    continue;
+                }
+                let matched = if rule.anchored {
+                    glob_match(&rule.pattern, &below)
+                } else {
+                    glob_match(&rule.pattern, &name)
+                };
+                if matched {
+                    ignored = !rule.negate;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+fn parse_ignore_file(path: &Path) -> Vec<IgnoreRule> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            // `**/x` is `x` at any depth; `x/**` everything inside `x`, which
+            // a `*` already covers since it crosses slashes here.
+            let line = line.strip_prefix("**/").unwrap_or(line);
+            let line = line.replace("/**", "/*");
+            let anchored = line.contains('/');
+            let pattern = line.trim_start_matches('/').to_string();
+            Some(IgnoreRule { pattern, negate, dir_only, anchored })
+        })
+        .collect()
+}
+
+/// Where `--git-cache` keeps its results: `$XDG_CACHE_HOME/rdir`, or
+/// `~/.cache/rdir`, named after a hash of the key.  `None` when neither is
+/// set to an absolute path.
+fn git_cache_file(key: &str) -> Option<PathBuf> {
+    use std::hash::{Hash, Hasher};
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .filter(|dir| dir.is_absolute())?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    Some(base.join("rdir").join(format!("git-{:016x}", hasher.finish())))
+}
+
+/// Reads a cache file written by `write_git_cache`.  Anything that is not a
+/// regular file owned by us and writable by nobody else is ignored, and a
+/// symlink in its place is never followed.
+fn read_git_cache(cache_file: &Path, key: &str) -> Option<GitMap> {
+    use std::io::Read;
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    let mut file = options.open(cache_file).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o022 != 0 {
+            return None;
+        }
+    }
+    if metadata.modified().ok()?.elapsed().ok()? > GIT_CACHE_TTL {
+        return None;
+    }
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents).ok()?;
+    
+    // NUL-separated fields, as paths may hold any other byte: the key, then
+    // per entry its two porcelain columns (with a `+` if a rename source
+    // follows), its path and that source.
+    let mut fields = contents.split(|&b| b == 0);
+    if fields.next()? != key.as_bytes() {
+        return None;
+    }
+    let mut map = HashMap::new();
+    while let Some(codes) = fields.next() {
+        if codes.is_empty() {
+            break;
+        }
+        let (x, y) = (char::from(*codes.first()?), char::from(*codes.get(1)?));
+        let path = path_from_bytes(fields.next()?);
+        let renamed_from = if codes.get(2) == Some(&b'+') { Some(path_from_bytes(fields.next()?)) } else { None };
+        let state = parse_git_state(x, y);
+        let flags = GitFlags { index: git_flag(x), worktree: git_flag(y) };
+        map.insert(path, GitEntry { state, flags, renamed_from });
+    }
+    Some(map)
+}
+
+/// Saves `map` for `read_git_cache`.  The file is written under a fresh
+/// name, created exclusively with mode 0600 in a directory only we can
+/// enter, and then renamed into place, so nothing planted at either name is
+/// ever written through.
+fn write_git_cache(cache_file: &Path, key: &str, map: &GitMap) {
+    let mut contents = Vec::new();
+    contents.extend_from_slice(key.as_bytes());
+    contents.push(0);
+    for (path, git) in map {
+        // Both porcelain columns; the combined state is derived again on read.
+        contents.push(git_flag_code(git.flags.index) as u8);
+        contents.push(git_flag_code(git.flags.worktree) as u8);
+        if git.renamed_from.is_some() {
+            contents.push(b'+');
+        }
+        contents.push(0);
+        contents.extend_from_slice(&path_bytes(path));
+        contents.push(0);
+        if let Some(from) = &git.renamed_from {
+            contents.extend_from_slice(&path_bytes(from));
+            contents.push(0);
+        }
+    }
+    // The cache is only an optimisation, so failing to write it is not an error.
+    let _ = write_private_file(cache_file, &contents);
+}
+
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().ok_or_else(|| io::Error::other("no parent directory"))?;
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = dir.join(name);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+    }
+    let written = options.open(&tmp).and_then(|mut file| file.write_all(contents));
+    let result = written.and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// The raw bytes of `path`, for formats that must not lose any.
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+/// Author of the most recent commit touching each entry of `path`, keyed by
+/// entry name.  A single `git log` walk covers the whole directory: the
+/// first time a name shows up is its latest commit, and a subdirectory is
+/// credited to whoever last touched anything inside it.
+fn git_last_authors(path: &Path) -> HashMap<OsString, String> {
+    let mut authors: HashMap<OsString, String> = HashMap::new();
+    let output = Command::new("git")
+        .args(["log", "--format=%x00%an", "--name-only", "--relative", "--", "."])
+        .current_dir(path)
+        .output();
+    let output = match output {
+        Ok(o) if o.status.success() => o,
+        _ => return authors,
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut author = "";
+    for line in stdout.lines() {
+        if let Some(name) = line.strip_prefix('\0') {
+            author = name;
+        } else if let Some(first) = Path::new(line).components().next() {
+            authors
+                .entry(first.as_os_str().to_owned())
+                .or_insert_with(|| author.to_string());
+        }
+    }
+    authors
+}
+
+/// Files directly in `path` that a commit within `window` added.  Renames
+/// are not additions, so a moved file keeps its age.
+fn git_recently_added(path: &Path, window: Duration) -> HashSet<PathBuf> {
+    let since = format!("--since={} seconds ago", window.as_secs());
+    let output = Command::new("git")
+        .args(["log", "--diff-filter=A", "--format=", "--name-only", "--relative"])
+        .arg(since)
+        .args(["--", "."])
+        .current_dir(path)
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// The levels a `--tree=N` or `--depth=N` listing shows; zero or a
+/// negative number means no limit.
+fn parse_tree_depth(val: &str) -> Option<usize> {
+    let num = val.parse::<isize>().ok()?;
+    Some(if num <= 0 { usize::MAX } else { num as usize })
+}
+
+/// Parses an age like `90s`, `45m`, `12h`, `7d` or `2w`; a bare number is
+/// taken as seconds.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let n: u64 = num.parse().ok()?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    n.checked_mul(secs).map(Duration::from_secs)
+}
+
+/// Every path `git status` reports for the repository holding `path`, by
+/// absolute path, including the ignored ones if `ignored` is set.
+fn git_statuses(path: &Path, ignored: bool) -> GitMap {
+    let mut map: GitMap = HashMap::new();
+    // Porcelain paths are relative to the top of the repository.
+    let top = match Command::new("git").args(["rev-parse", "--show-toplevel"]).current_dir(path).output() {
+        Ok(o) if o.status.success() => path_from_bytes(o.stdout.strip_suffix(b"\n").unwrap_or(&o.stdout)),
+        _ => return map,
+    };
+    let mut command = Command::new("git");
+    command.args(["status", "--porcelain", "-z"]).current_dir(path);
+    if ignored {
+        command.arg("--ignored");
+    }
+    let output = command.output();
+    let output = match output {
+        Ok(o) if o.status.success() => o.stdout,
+        _ => return map,
+    };
+    // With -z every path comes verbatim and NUL-terminated; a rename or copy
+    // is followed by the path it came from.
+    let mut records = output.split(|&b| b == 0);
+    while let Some(record) = records.next() {
+        if record.len() < 4 {
+            continue;
+        }
+        let x = record[0] as char;
+        let y = record[1] as char;
+        let renamed_from = if matches!(x, 'R' | 'C') || matches!(y, 'R' | 'C') {
+            records.next().map(path_from_bytes)
+        } else {
+            None
+        };
+        let state = parse_git_state(x, y);
+        let flags = GitFlags { index: git_flag(x), worktree: git_flag(y) };
+        // An ignored or untracked directory comes with a trailing slash.
+        let rel = record[3..].strip_suffix(b"/").unwrap_or(&record[3..]);
+        map.insert(top.join(path_from_bytes(rel)), GitEntry { state, flags, renamed_from });
+    }
+    map
+}
+
+fn parse_git_state(x: char, y: char) -> GitState {
+    if x == 'U' || y == 'U' || (x == y && (x == 'A' || x == 'D')) {
+        return GitState::Conflicted;
+    }
+    git_flag(if x != ' ' { x } else { y })
+}
+
+/// What one porcelain status column says on its own.
+fn git_flag(c: char) -> GitState {
+    match c {
+        'A' | 'C' => GitState::Added,
+        'M' => GitState::Modified,
+        'D' => GitState::Deleted,
+        'R' => GitState::Renamed,
+        'T' => GitState::TypeChanged,
+        '?' => GitState::Untracked,
+        '!' => GitState::Ignored,
+        'U' => GitState::Conflicted,
+        _ => GitState::None,
+    }
+}
+
+/// The porcelain column letter `git_flag` reads back as `state`.
+fn git_flag_code(state: GitState) -> char {
+    match state {
+        GitState::Added => 'A',
+        GitState::Modified => 'M',
+        GitState::Deleted => 'D',
+        GitState::Renamed => 'R',
+        GitState::TypeChanged => 'T',
+        GitState::Untracked => '?',
+        GitState::Ignored => '!',
+        GitState::Conflicted => 'U',
+        GitState::None => ' ',
+    }
+}
+
+fn parse_perm_filter(s: &str) -> Option<PermFilter> {
+    let (ctor, digits): (fn(u32) -> PermFilter, &str) = if let Some(rest) = s.strip_prefix('/') {
+        (PermFilter::Any, rest)
+    } else if let Some(rest) = s.strip_prefix('-') {
+        (PermFilter::All, rest)
+    } else {
+        (PermFilter::Exact, s)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    match u32::from_str_radix(digits, 8) {
+        Ok(mask) if mask <= 0o7777 => Some(ctor(mask)),
+        _ => None,
+    }
+}
+
+fn perm_matches(filter: PermFilter, metadata: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o7777;
+        match filter {
+            PermFilter::Any(mask) => mask == 0 || mode & mask != 0,
+            PermFilter::All(mask) => mode & mask == mask,
+            PermFilter::Exact(mask) => mode == mask,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (filter, metadata);
+        true
+    }
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    // SAFETY: getpwuid returns null or a record in static storage that stays
+    // valid until the next call; the name is copied out before then.
+    unsafe {
+        let pw = libc::getpwuid(uid);
+        if pw.is_null() {
+            return None;
+        }
+        Some(std::ffi::CStr::from_ptr((*pw).pw_name).to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> Option<String> {
+    // SAFETY: as for getpwuid above.
+    unsafe {
+        let gr = libc::getgrgid(gid);
+        if gr.is_null() {
+            return None;
+        }
+        Some(std::ffi::CStr::from_ptr((*gr).gr_name).to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(unix)]
+fn uid_for_name(name: &str) -> Option<u32> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as for getpwuid; only the uid is read from the record.
+    unsafe {
+        let pw = libc::getpwnam(c_name.as_ptr());
+        if pw.is_null() {
+            None
+        } else {
+            Some((*pw).pw_uid)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn gid_for_name(name: &str) -> Option<u32> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as for getpwuid; only the gid is read from the record.
+    unsafe {
+        let gr = libc::getgrnam(c_name.as_ptr());
+        if gr.is_null() {
+            None
+        } else {
+            Some((*gr).gr_gid)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn group_name(_gid: u32) -> Option<String> {
+    None
+}
+
+/// Names cannot be resolved off unix, where `--owner` is a no-op anyway.
+#[cfg(not(unix))]
+fn uid_for_name(_name: &str) -> Option<u32> {
+    Some(0)
+}
+
+#[cfg(not(unix))]
+fn gid_for_name(_name: &str) -> Option<u32> {
+    Some(0)
+}
+
+/// The permission bits as four octal digits, e.g. `4755` for a setuid
+/// program.  Always four wide so the column needs no padding.
+fn octal_mode(metadata: &Metadata) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        format!("{:04o}", metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        "----".to_string()
+    }
+}
+
+/// The nine `rwx` characters for `mode`.  Setuid, setgid and the sticky
+/// bit take the execute slot of the user, group and others as `s`, `s` and
+/// `t`, in capitals when that execute bit is not set.
+fn mode_string(mode: u32) -> String {
+    let mut s = String::with_capacity(9);
+    for (shift, special, lower, upper) in [(6, 0o4000, 's', 'S'), (3, 0o2000, 's', 'S'), (0, 0o1000, 't', 'T')] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => lower,
+            (false, true) => upper,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+/// What `ls -l` puts after the permissions: `@` when `path` has extended
+/// attributes, `+` when it has an ACL, and a blank otherwise.  SELinux
+/// labels are on nearly everything, so they do not count.
+fn xattr_marker(path: &Path) -> char {
+    let names = xattr_names(path);
+    let is_acl = |name: &[u8]| name.starts_with(b"system.posix_acl_");
+    if names.iter().any(|n| !is_acl(n) && n.as_slice() != b"security.selinux") {
+        '@'
+    } else if names.iter().any(|n| is_acl(n)) {
+        '+'
+    } else {
+        ' '
+    }
+}
+
+/// Names of the extended attributes of `path` itself (symlinks are not
+/// followed).  Empty where the platform has no xattr support.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn xattr_names(path: &Path) -> Vec<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = match std::ffi::CString::new(path.as_os_str().as_bytes()) {
+        Ok(p) => p,
+        Err(_) => return Vec::new(),
+    };
+    // SAFETY: `c_path` is NUL-terminated and `buf` is `buf.len()` bytes
+    // long; a null buffer of size 0 only asks for the size needed.
+    let list = |buf: &mut [u8]| unsafe {
+        let ptr = if buf.is_empty() { std::ptr::null_mut() } else { buf.as_mut_ptr().cast() };
+        #[cfg(target_os = "linux")]
+        let len = libc::llistxattr(c_path.as_ptr(), ptr, buf.len());
+        #[cfg(target_os = "macos")]
+        let len = libc::listxattr(c_path.as_ptr(), ptr, buf.len(), libc::XATTR_NOFOLLOW);
+        len
+    };
+    let size = list(&mut []);
+    if size <= 0 {
+        return Vec::new();
+    }
+    let mut buf = vec![0_u8; size as usize];
+    let len = list(&mut buf);
+    if len <= 0 {
+        return Vec::new();
+    }
+    buf.truncate(len as usize);
+    buf.split(|&b| b == 0).filter(|n| !n.is_empty()).map(<[u8]>::to_vec).collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn xattr_names(path: &Path) -> Vec<Vec<u8>> {
+    let _ = path;
+    Vec::new()
+}
+
+fn perm_string(file_type: &FileType, metadata: &Metadata) -> String {
+    let mut s = String::new();
+    
+    let type_char = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                'p'
+            } else if file_type.is_socket() {
+                's'
+            } else if file_type.is_block_device() {
+                'b'
+            } else if file_type.is_char_device() {
+                'c'
+            } else {
+                '-'
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            '-'
+        }
+    };
+    s.push(type_char);
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        s.push_str(&mode_string(metadata.permissions().mode()));
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        for _ in 0..9 {
+            s.push('-');
+        }
+    }
+    s
+}
+
+/// Formats a timestamp in the `--time-style` picked.
+fn format_time(st: SystemTime, opts: &Options) -> String {
+    match opts.time_style {
+        TimeStyle::Default => format_calendar_time(st, opts.utc, false),
+        TimeStyle::Iso => format_calendar_time(st, opts.utc, true),
+        TimeStyle::Relative => format_relative_time(st, SystemTime::now()),
+    }
+}
+
+/// Formats a timestamp as `YYYY-MM-DD HH:MM`, or with `iso` as
+/// `YYYY-MM-DDTHH:MM:SS` plus the UTC offset.  Times are in the local
+/// timezone, or in UTC with `utc` or when the local offset is unknown.
+fn format_calendar_time(st: SystemTime, utc: bool, iso: bool) -> String {
+    let duration = match st.duration_since(UNIX_EPOCH) {
+        Ok(d) => d,
+        Err(e) => e.duration(),
+    };
+    let mut secs = duration.as_secs();
+    let offset = if utc { 0 } else { local_utc_offset(secs).unwrap_or(0) };
+    secs = secs.saturating_add_signed(offset);
+    let days = secs / 86_400;
+    let mut rem_secs = secs % 86_400;
+    let hour = (rem_secs / 3_600) as u32;
+    rem_secs %= 3_600;
+    let minute = (rem_secs / 60) as u32;
+    let second = (rem_secs % 60) as u32;
+    
+    let mut year: i32 = 1970;
+    let mut day_count = days as i64;
+    
+    loop {
+        let leap = is_leap_year(year);
+        let year_days = if leap { 366 } else { 365 };
+        if day_count >= year_days {
+            day_count -= year_days;
+            year += 1;
+        } else {
+            break;
+        }
+    }
+    
+    let month_lengths = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let month_lengths_leap = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let months = if is_leap_year(year) { &month_lengths_leap } else { &month_lengths };
+    let mut month: usize = 0;
+    while day_count >= months[month] as i64 {
+        day_count -= months[month] as i64;
+        month += 1;
+    }
+    let day = day_count + 1;
+    if !iso {
+        return format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month + 1, day, hour, minute);
+    }
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let abs = offset.unsigned_abs();
+        format!("{}{:02}:{:02}", sign, abs / 3_600, abs % 3_600 / 60)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month + 1,
+        day,
+        hour,
+        minute,
+        second,
+        zone
+    )
+}
+
+/// Formats how long ago `st` was as a count of its largest whole unit, such
+/// as `2h` or `5mo`, right-aligned to four columns.  Times after `now` get a
+/// `+` in front.
+fn format_relative_time(st: SystemTime, now: SystemTime) -> String {
+    let (secs, sign) = match now.duration_since(st) {
+        Ok(d) => (d.as_secs(), ""),
+        Err(e) => (e.duration().as_secs(), "+"),
+    };
+    const UNITS: [(u64, &str); 6] = [
+        (365 * 86_400, "y"),
+        (30 * 86_400, "mo"),
+        (7 * 86_400, "w"),
+        (86_400, "d"),
+        (3_600, "h"),
+        (60, "m"),
+    ];
+    let (count, unit) = UNITS
+        .iter()
+        .find(|&&(len, _)| secs >= len)
+        .map_or((secs, "s"), |&(len, unit)| (secs / len, unit));
+    format!("{:>4}", format!("{}{}{}", sign, count, unit))
+}
+
+/// Seconds east of UTC for the local timezone at `secs` past the epoch, as
+/// localtime_r(3) works it out from TZ or /etc/localtime.
+#[cfg(unix)]
+fn local_utc_offset(secs: u64) -> Option<i64> {
+    let t = libc::time_t::try_from(secs).ok()?;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: localtime_r only reads `t` and writes the `tm` we pass.
+    let ret = unsafe { libc::localtime_r(&t, &mut tm) };
+    if ret.is_null() {
+        None
+    } else {
+        Some(tm.tm_gmtoff as i64)
+    }
+}
+
+#[cfg(not(unix))]
+fn local_utc_offset(_secs: u64) -> Option<i64> {
+    None
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+}
+
+const SIZE_UNITS: [&str; 9] = ["B", "K", "M", "G", "T", "P", "E", "Z", "Y"];
+/// Decimal units for `--si`, in steps of 1000 rather than 1024.
+const SI_SIZE_UNITS: [&str; 9] = ["B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+
+fn format_size(size: u64, human_readable: bool, si: bool) -> String {
+    if !human_readable {
+        return size.to_string();
+    }
+    let (number, unit) = size_parts_in_unit(size, size_unit_for(size, si), si);
+    format!("{}{}", number, unit)
+}
+
+/// The figure on the `total` line of a long listing: with `--blocks` the
+/// space taken as a size, otherwise the count of `--block-size` blocks with
+/// each entry rounded up on its own, as ls does.
+#[cfg(unix)]
+fn total_blocks(entries: &[EntryInfo], opts: &Options) -> String {
+    if opts.blocks {
+        let total: u64 = entries.iter().map(|info| allocated_size(&info.metadata)).sum();
+        format_size(total, opts.human_readable, opts.si)
+    } else {
+        let total: u64 = entries
+            .iter()
+            .map(|info| allocated_size(&info.metadata).div_ceil(opts.block_size))
+            .sum();
+        total.to_string()
+    }
+}
+
+/// Bytes actually allocated to an entry on disk, which is less than its
+/// length for a sparse file.
+#[cfg(unix)]
+fn allocated_size(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+/// Index into `SIZE_UNITS` (or `SI_SIZE_UNITS`) of the largest unit that
+/// keeps `size` at or above 1.
+fn size_unit_for(size: u64, si: bool) -> usize {
+    let base = if si { 1000.0 } else { 1024.0 };
+    let mut s = size as f64;
+    let mut idx = 0;
+    while s >= base && idx < SIZE_UNITS.len() - 1 {
+        s /= base;
+        idx += 1;
+    }
+    idx
+}
+
+/// `size` in unit `idx`, as the number and the unit suffix.
+fn size_parts_in_unit(size: u64, idx: usize, si: bool) -> (String, &'static str) {
+    let (base, units): (f64, &[&'static str; 9]) = if si { (1000.0, &SI_SIZE_UNITS) } else { (1024.0, &SIZE_UNITS) };
+    if idx == 0 {
+        (size.to_string(), units[idx])
+    } else {
+        let s = size as f64 / base.powi(idx as i32);
+        // Only a shared --uniform-size-unit can leave a size this far below
+        // one unit; show decimals down to its first significant digit so it
+        // never prints as zero.
+        let precision = if s > 0.0 && s < 0.1 {
+            let digits = (-s.log10()).ceil() as i32;
+            // Rounding can carry into the digit before, as 0.0000095 does.
+            if (s * 10f64.powi(digits)).round() >= 10.0 { (digits - 1).max(1) as usize } else { digits as usize }
+        } else {
+            1
+        };
+        (format!("{:.*}", precision, s), units[idx])
+    }
+}
+
+/// Terminal columns taken by `s`, skipping ANSI colour sequences.
+fn visible_len(s: &str) -> usize {
+    let mut len = 0;
+    let mut in_escape = false;
+    let mut chars = s.chars().peekable();
+    let mut prev_width = 0;
+    while let Some(c) = chars.next() {
+        if in_escape {
+            if c == 'm' {
+                in_escape = false;
+            }
+            continue;
+        }
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            in_escape = true;
+            continue;
+        }
+        // VS16 asks for emoji presentation, which terminals draw two wide.
+        if c == '\u{fe0f}' && prev_width == 1 {
+            len += 1;
+            prev_width = 2;
+            continue;
+        }
+        prev_width = char_width(c);
+        len += prev_width;
+    }
+    len
+}
+
+/// Columns a terminal gives `c`: zero for combining marks and other
+/// zero-width characters, two for East Asian wide characters and emoji,
+/// one for everything else.
+fn char_width(c: char) -> usize {
+    let cp = c as u32;
+    match cp {
+        0x0300..=0x036F
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0x2060..=0x2064
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F
+        | 0xFEFF
+        | 0xE0100..=0xE01EF => 0,
+        0x1100..=0x115F
+        | 0x231A..=0x231B
+        | 0x23E9..=0x23EC
+        | 0x23F0
+        | 0x23F3
+        | 0x25FD..=0x25FE
+        | 0x2614..=0x2615
+        | 0x2648..=0x2653
+        | 0x267F
+        | 0x2693
+        | 0x26A1
+        | 0x26AA..=0x26AB
+        | 0x26BD..=0x26BE
+        | 0x26C4..=0x26C5
+        | 0x26CE
+        | 0x26D4
+        | 0x26EA
+        | 0x26F2..=0x26F3
+        | 0x26F5
+        | 0x26FA
+        | 0x26FD
+        | 0x2705
+        | 0x270A..=0x270B
+        | 0x2728
+        | 0x274C
+        | 0x274E
+        | 0x2753..=0x2755
+        | 0x2757
+        | 0x2795..=0x2797
+        | 0x27B0
+        | 0x27BF
+        | 0x2B1B..=0x2B1C
+        | 0x2B50
+        | 0x2B55
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F000..=0x1FAFF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn pad_left(s: &str, width: usize) -> String {
+    let vis_len = visible_len(s);
+    let mut padded = String::new();
+    for _ in vis_len..width {
+        padded.push(' ');
+    }
+    padded.push_str(s);
+    padded
+}
+
+/// Width available for the grid layout.  Queried afresh for every listing
+/// rather than once at startup, so a terminal resized between listings is
+/// picked up by the next one.
+///
+/// The terminal itself is asked first since shells rarely export `COLUMNS`
+/// to child processes; when stdout is not a terminal `COLUMNS` and then 80
+/// are used, so piped output stays the same as before.
+fn detect_terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        if let Some(width) = ioctl_terminal_width() {
+            return width;
+        }
+    }
+    match env::var("COLUMNS") {
+        Ok(val) => val.parse().unwrap_or(80),
+        Err(_) => 80,
+    }
+}
+
+#[cfg(unix)]
+fn ioctl_terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` into the pointer we pass.
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+fn pad_right(s: &str, width: usize) -> String {
+    let mut padded = s.to_string();
+    for _ in visible_len(s)..width {
+        padded.push(' ');
+    }
+    padded
+}
+
+/// Spaces between grid columns.
+const GRID_GAP: usize = 2;
+
+/// Picks the most columns that fit in `term_width` when every column is
+/// only as wide as its own widest entry, as `ls` does.  Returns the number
+/// of rows and the width of each column, gaps not included.
+fn grid_layout(widths: &[usize], term_width: usize, across: bool) -> (usize, Vec<usize>) {
+    let n = widths.len();
+    let max_cols = (term_width / (1 + GRID_GAP)).clamp(1, n.max(1));
+    for cols in (1..=max_cols).rev() {
+        let rows = n.div_ceil(cols);
+        let mut col_widths = vec![0; cols];
+        for r in 0..rows {
+            for (c, col_width) in col_widths.iter_mut().enumerate() {
+                let idx = grid_index(r, c, rows, cols, across);
+                if idx < n {
+                    *col_width = (*col_width).max(widths[idx]);
+                }
+            }
+        }
+        // Filling down can leave the last columns empty.
+        while col_widths.len() > 1 && col_widths.last() == Some(&0) {
+            col_widths.pop();
+        }
+        let total = col_widths.iter().sum::<usize>() + GRID_GAP * (col_widths.len() - 1);
+        if total <= term_width || cols == 1 {
+            return (rows, col_widths);
+        }
+    }
+    unreachable!("a single column always fits")
+}
+
+/// Entry shown at row `r`, column `c` of a `rows` by `cols` grid that is
+/// filled column by column, or row by row with `across`.
+fn grid_index(r: usize, c: usize, rows: usize, cols: usize, across: bool) -> usize {
+    if across {
+        r * cols + c
+    } else {
+        r + c * rows
+    }
+}
+
+/// Reads the entries of `dir` that pass every filter, counting them as it
+/// goes.  Returns `None`, after reporting the error, if the directory
+/// cannot be read.
+///
+/// `emit`, if given, is handed each entry as soon as it is read and
+/// accepted, and nothing is kept: the returned list is empty.
+fn read_entries(dir: &Path, opts: &Options, git_map: &GitMap, counts: &mut Counts, progress: &mut Progress, emit: Option<&mut dyn FnMut(EntryInfo)>) -> Option<Vec<EntryInfo>> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(e) => {
+            eprintln!("rdir: cannot access {}: {}", dir.display(), e);
+            return None;
+        }
+    };
+    
+    let recently_added = match opts.new_within {
+        Some(window) => git_recently_added(dir, window),
+        None => HashSet::new(),
+    };
+    let git_dir = if git_map.is_empty() { None } else { fs::canonicalize(dir).ok() };
+    let reader = EntryReader { opts, git_map, git_dir, recently_added };
+    let mut seen_inodes: HashMap<(u64, u64), usize> = HashMap::new();
+    
+    if let Some(emit) = emit {
+        // Each entry is stat'ed and handed on before the next is read.
+        for res in read_dir {
+            progress.tick();
+            let (file_name, path) = match reader.name(res) {
+                Some(named) => named,
+                None => continue,
+            };
+            let metadata = match reader.stat(&path, entry_metadata(&path, opts)) {
+                Some(md) => md,
+                None => continue,
+            };
+            if opts.dedup && dev_ino(&metadata).is_some_and(|key| seen_inodes.insert(key, 0).is_some()) {
+                continue;
+            }
+            if let Some(info) = reader.entry(file_name, path, metadata, counts, progress) {
+                emit(info);
+            }
+        }
+        return Some(Vec::new());
+    }
+    
+    // Names are read and filtered first so the stat calls, which dominate
+    // in large directories, can be spread over threads.
+    let mut names: Vec<OsString> = Vec::new();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for res in read_dir {
+        progress.tick();
+        if let Some((file_name, path)) = reader.name(res) {
+            paths.push(path);
+            names.push(file_name);
+        }
+    }
+    let stats = stat_entries(&paths, opts);
+    
+    let mut entries: Vec<EntryInfo> = Vec::new();
+    for ((file_name, path), stat) in names.into_iter().zip(paths).zip(stats) {
+        let metadata = match reader.stat(&path, stat) {
+            Some(md) => md,
+            None => continue,
+        };
+        if opts.dedup {
+            if let Some(key) = dev_ino(&metadata) {
+                if let Some(&kept) = seen_inodes.get(&key) {
+                    entries[kept].hidden_links += 1;
+                    continue;
+                }
+                seen_inodes.insert(key, entries.len());
+            }
+        }
+        if let Some(info) = reader.entry(file_name, path, metadata, counts, progress) {
+            entries.push(info);
+        }
+    }
+    
+    Some(entries)
+}
+
+/// What `read_entries` needs to judge and build each entry of one directory.
+struct EntryReader<'a> {
+    opts: &'a Options,
+    git_map: &'a GitMap,
+    /// The canonical directory, to look entries up in `git_map` by.
+    git_dir: Option<PathBuf>,
+    recently_added: HashSet<PathBuf>,
+}
+
+impl EntryReader<'_> {
+    /// The name and path of a directory entry, unless it is filtered out
+    /// by name alone.
+    fn name(&self, res: io::Result<fs::DirEntry>) -> Option<(OsString, PathBuf)> {
+        let entry = match res {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("rdir: error reading directory: {}", e);
+                return None;
+            }
+        };
+        let file_name = entry.file_name();
+        if !self.opts.all && file_name.to_string_lossy().starts_with('.') {
+            return None;
+        }
+        if self.opts.git_ignore && self.git_entry(&file_name).is_some_and(|git| git.state == GitState::Ignored) {
+            return None;
+        }
+        if file_name.to_str().is_none() && rejects_name(&entry.path(), self.opts) {
+            return None;
+        }
+        Some((file_name, entry.path()))
+    }
+
+    fn git_entry(&self, file_name: &OsString) -> Option<&GitEntry> {
+        self.git_dir.as_ref().and_then(|d| self.git_map.get(&d.join(file_name)))
+    }
+
+    /// The metadata from `stat`, or `None` after reporting why there is none.
+    fn stat(&self, path: &Path, stat: io::Result<Metadata>) -> Option<Metadata> {
+        match stat {
+            Ok(md) => Some(md),
+            Err(e) => {
+                eprintln!("rdir: cannot access {}: {}", path.display(), e);
+                EXIT_FAILURE.store(true, AtomicOrdering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// The entry for `path`, counted, unless a filter that needs its
+    /// metadata leaves it out.
+    fn entry(&self, file_name: OsString, path: PathBuf, metadata: Metadata, counts: &mut Counts, progress: &mut Progress) -> Option<EntryInfo> {
+        let opts = self.opts;
+        let file_type = metadata.file_type();
+        
+        if opts.dirs_only && !file_type.is_dir() {
+            return None;
+        }
+        if opts.files_only && file_type.is_dir() {
+            return None;
+        }
+        if !passes_globs(&file_name.to_string_lossy(), file_type.is_dir(), opts) {
+            return None;
+        }
+        if opts.broken_links == BrokenLinks::Hide
+            && file_type.is_symlink()
+            && is_broken_symlink(&path)
+        {
+            return None;
+        }
+        if let Some(filter) = opts.perm_filter {
+            if !perm_matches(filter, &metadata) {
+                return None;
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if opts.owner_filter.is_some_and(|uid| metadata.uid() != uid)
+                || opts.group_filter.is_some_and(|gid| metadata.gid() != gid)
+            {
+                return None;
+            }
+        }
+        
+        // --git-ignore alone loads the statuses without showing them.
+        let git_entry = self.git_entry(&file_name).filter(|_| opts.git_status);
+        let (git_state, git_flags, renamed_from) = match git_entry {
+            Some(git) => (git.state, git.flags, git.renamed_from.clone()),
+            None => (GitState::None, GitFlags::CLEAN, None),
+        };
+        let mut kind = symbols::get_file_kind(&file_type, &path);
+        if opts.smart_dir_icons && file_type.is_dir() {
+            kind = symbols::dominant_kind(&path).unwrap_or(kind);
+        }
+        let icon = icon_for(kind, opts);
+        count_entry(counts, &metadata, &path, opts);
+        
+        let is_new = self.recently_added.contains(Path::new(&file_name));
+        let total_size = if opts.total_size && file_type.is_dir() {
+            Some(dir_total_size(&path, progress))
+        } else {
+            None
+        };
+        Some(EntryInfo {
+            path,
+            name: file_name,
+            metadata,
+            icon,
+            git_state,
+            git_flags,
+            renamed_from,
+            hidden_links: 0,
+            link_group: None,
+            is_new,
+            total_size,
+        })
+    }
+}
+
+fn sort_entries(entries: &mut [EntryInfo], opts: &Options, dirs_first: bool, files_first: bool) {
+    if opts.sort_key != SortKey::None {
+        entries.sort_by(|a, b| {
+            let a_dir = a.metadata.file_type().is_dir();
+            let b_dir = b.metadata.file_type().is_dir();
+            group_order(a_dir, b_dir, dirs_first, files_first)
+                .then_with(|| dotfile_order(a, b, opts))
+                .then_with(|| {
+                    let ord = compare_entries(a, b, opts);
+                    if opts.group_reverse { ord.reverse() } else { ord }
+                })
+        });
+    }
+    if opts.reverse {
+        entries.reverse();
+    }
+}
+
+/// Order of two entries under `--sd`/`--sf` alone.  The sort key only
+/// decides between entries this calls equal, so the groups never mix.
+fn group_order(a_dir: bool, b_dir: bool, dirs_first: bool, files_first: bool) -> std::cmp::Ordering {
+    if a_dir == b_dir || !(dirs_first || files_first) {
+        return std::cmp::Ordering::Equal;
+    }
+    if a_dir == dirs_first {
+        std::cmp::Ordering::Less
+    } else {
+        std::cmp::Ordering::Greater
+    }
+}
+/// `--dotfiles=first|last`: hidden entries before or after the rest.  Kept
+/// apart from `compare_entries` so `--group-reverse` leaves it alone.
+fn dotfile_order(a: &EntryInfo, b: &EntryInfo, opts: &Options) -> std::cmp::Ordering {
+    let a_hidden = a.name.as_encoded_bytes().starts_with(b".");
+    let b_hidden = b.name.as_encoded_bytes().starts_with(b".");
+    if opts.dotfiles == DotfilesPosition::Mixed || a_hidden == b_hidden {
+        return std::cmp::Ordering::Equal;
+    }
+    let hidden_first = if a_hidden { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater };
+    if opts.dotfiles == DotfilesPosition::First { hidden_first } else { hidden_first.reverse() }
+}
+
+/// Lists `path` and returns the subdirectories in it, in listing order, for
+/// `-R` to descend into.
+fn list_dir(path: &Path, opts: &Options, counts: &mut Counts, progress: &mut Progress, out: &mut dyn Write) -> io::Result<Vec<PathBuf>> {
+    let git_map = if opts.git_status || opts.git_ignore {
+        load_git_statuses(path, opts)
+    } else {
+        HashMap::new()
+    };
+    
+    if streams(opts) {
+        let mut written = Ok(());
+        let mut subdirs = Vec::new();
+        let mut emit = |info: EntryInfo| {
+            let mut line = build_short_display(&info, opts);
+            if let Some(terminator) = &opts.entry_terminator {
+                line.push_str(terminator);
+            }
+            if written.is_ok() {
+                written = writeln!(out, "{}", line);
+            }
+            // -R only needs to know where to go next.
+            if opts.recursive && enters(&info) {
+                subdirs.push(info.path);
+            }
+        };
+        // The entries appearing are progress enough.
+        read_entries(path, opts, &git_map, counts, &mut Progress::new(false, false), Some(&mut emit));
+        written?;
+        return Ok(subdirs);
+    }
+    
+    let mut entries = match read_entries(path, opts, &git_map, counts, progress, None) {
+        Some(entries) => entries,
+        None => return Ok(Vec::new()),
+    };
+    sort_entries(&mut entries, opts, opts.sort_dirs_first, opts.sort_files_first);
+    if opts.hard_links {
+        number_hard_links(&mut entries);
+    }
+    let subdirs: Vec<PathBuf> = entries.iter().filter(|info| enters(info)).map(|info| info.path.clone()).collect();
+    
+    progress.clear();
+    
+    if opts.summary_json {
+        return Ok(subdirs);
+    }
+    
+    print_entries(entries, opts, out, true)?;
+    Ok(subdirs)
+}
+
+/// Prints a sorted listing in the chosen format.  `show_total` adds the
+/// `total` line to a long listing.
+fn print_entries(entries: Vec<EntryInfo>, opts: &Options, out: &mut dyn Write, show_total: bool) -> io::Result<()> {
+    if opts.json {
+        let objects: Vec<String> = entries.iter().map(|info| entry_json(info, None, opts)).collect();
+        writeln!(out, "[{}]", objects.join(","))?;
+        return Ok(());
+    }
+    
+    if opts.csv {
+        writeln!(out, "{}", CSV_HEADER)?;
+        for info in &entries {
+            writeln!(out, "{}", csv_row(info, &display_path(Path::new(&info.name), opts), None, opts))?;
+        }
+        return Ok(());
+    }
+    
+    if opts.long0 {
+        for info in &entries {
+            write_long0_record(out, info)?;
+        }
+        return Ok(());
+    }
+    
+    if opts.long {
+        let mut layout = LongLayout::default();
+        
+        if opts.uniform_size_unit && opts.human_readable {
+            let max_size = entries.iter().map(entry_size).max().unwrap_or(0);
+            layout.size_unit = Some(size_unit_for(max_size, opts.si));
+        }
+        
+        for info in &entries {
             let links: u64 = {
                 #[cfg(unix)]
                 {
                     use std::os::unix::fs::MetadataExt;
-                    info.metadata.nlink() as u64
+                    info.metadata.nlink()
                 }
                 #[cfg(not(unix))]
                 {
                     1
                 }
             };
-            link_w = link_w.max(format!("{}", links).len());
+            layout.link_w = layout.link_w.max(format!("{}", links).len());
             
             #[cfg(unix)]
             {
                 use std::os::unix::fs::MetadataExt;
                 let uid = info.metadata.uid();
                 let gid = info.metadata.gid();
-                uid_w = uid_w.max(format!("{}", uid).len());
-                gid_w = gid_w.max(format!("{}", gid).len());
+                layout.resolve_ids(uid, gid, opts);
+                layout.uid_w = layout.uid_w.max(visible_len(&layout.user(uid)));
+                layout.gid_w = layout.gid_w.max(visible_len(&layout.group(gid)));
             }
             #[cfg(not(unix))]
             {
-                uid_w = uid_w.max(1);
-                gid_w = gid_w.max(1);
+                layout.uid_w = layout.uid_w.max(1);
+                layout.gid_w = layout.gid_w.max(1);
+            }
+            
+            if opts.bytes_aligned {
+                let (number, unit) = layout.size_parts(entry_size(info), opts);
+                layout.size_w = layout.size_w.max(number.len());
+                layout.size_unit_w = layout.size_unit_w.max(unit.len());
+            } else {
+                let size_str = layout.format_size(entry_size(info), opts);
+                layout.size_w = layout.size_w.max(size_str.len());
+            }
+
+            #[cfg(unix)]
+            if opts.blocks {
+                let blocks = format_size(allocated_size(&info.metadata), opts.human_readable, opts.si);
+                layout.blocks_w = layout.blocks_w.max(blocks.len());
+            }
+
+            if opts.inode {
+                layout.inode_w = layout.inode_w.max(inode_label(&info.metadata).len());
+            }
+        }
+        
+        // Entries named on the command line can live in different
+        // directories, so both columns are worked out per parent directory.
+        if opts.blame {
+            let mut by_dir: HashMap<PathBuf, HashMap<OsString, String>> = HashMap::new();
+            let mut authors: HashMap<PathBuf, String> = HashMap::new();
+            for info in &entries {
+                let dir_authors = by_dir.entry(parent_dir(&info.path)).or_insert_with_key(|dir| git_last_authors(dir));
+                if let Some(author) = info.path.file_name().and_then(|name| dir_authors.get(name)) {
+                    authors.insert(info.path.clone(), author.clone());
+                }
+            }
+            layout.blame_w = entries
+                .iter()
+                .map(|info| authors.get(&info.path).map_or(1, |a| visible_len(a)))
+                .max()
+                .unwrap_or(0);
+            layout.blame = Some(authors);
+        }
+        
+        if opts.show_device {
+            let mounts = if opts.resolve_mounts { read_mounts() } else { Vec::new() };
+            let mut dirs: HashMap<PathBuf, PathBuf> = HashMap::new();
+            let devices: HashMap<PathBuf, String> = entries
+                .iter()
+                .map(|info| {
+                    let dir = dirs
+                        .entry(parent_dir(&info.path))
+                        .or_insert_with_key(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()));
+                    let real = info.path.file_name().map_or_else(|| dir.clone(), |name| dir.join(name));
+                    (info.path.clone(), device_label(&info.metadata, &real, &mounts))
+                })
+                .collect();
+            layout.device_w = devices.values().map(|d| visible_len(d)).max().unwrap_or(0);
+            layout.devices = Some(devices);
+        }
+        
+        #[cfg(unix)]
+        if show_total {
+            writeln!(out, "total {}", total_blocks(&entries, opts))?;
+        }
+
+        let mut pager = Pager::new(opts, entries.len());
+        for info in entries {
+            print_long_entry(info, &layout, opts, out)?;
+            pager.after_entries(1, out)?;
+        }
+    } else {
+        let mut display_strings: Vec<String> = Vec::new();
+        let mut widths: Vec<usize> = Vec::new();
+        
+        let inode_w = if opts.inode {
+            entries.iter().map(|info| inode_label(&info.metadata).len()).max().unwrap_or(0)
+        } else {
+            0
+        };
+        for info in &entries {
+            let mut s = build_short_display(info, opts);
+            if opts.inode {
+                s = format!("{} {}", inode_column(&info.metadata, inode_w, opts), s);
+            }
+            widths.push(visible_len(&s));
+            display_strings.push(s);
+        }
+        
+        if opts.one_per_line {
+            if let Some(terminator) = &opts.entry_terminator {
+                for s in &mut display_strings {
+                    s.push_str(terminator);
+                }
+            }
+        }
+        
+        // Each page is laid out as a grid of its own, so a page ends with
+        // its last entry whatever the column order.
+        let mut pager = Pager::new(opts, display_strings.len());
+        let page_len = pager.page_len();
+        for (page, page_widths) in display_strings.chunks(page_len).zip(widths.chunks(page_len)) {
+            let (rows, col_widths) = if opts.one_per_line {
+                (page.len(), vec![0])
+            } else {
+                let term_width = opts.width.unwrap_or_else(detect_terminal_width);
+                grid_layout(page_widths, term_width, opts.across)
+            };
+            let cols = col_widths.len();
+            for r in 0..rows {
+                let mut line = String::new();
+                for (c, &col_width) in col_widths.iter().enumerate() {
+                    let idx = grid_index(r, c, rows, cols, opts.across);
+                    if idx < page.len() {
+                        line.push_str(&page[idx]);
+                        // Nothing follows the last entry of a row, so no padding.
+                        if c + 1 < cols && grid_index(r, c + 1, rows, cols, opts.across) < page.len() {
+                            for _ in page_widths[idx]..col_width + GRID_GAP {
+                                line.push(' ');
+                            }
+                        }
+                    }
+                }
+                writeln!(out, "{}", line)?;
+            }
+            pager.after_entries(page.len(), out)?;
+        }
+    }
+    Ok(())
+}
+
+/// `-R`: lists `path`, then every directory below it depth-first, each under
+/// its own header.
+fn list_recursive(path: &Path, opts: &Options, counts: &mut Counts, progress: &mut Progress, out: &mut dyn Write) -> io::Result<()> {
+    for subdir in list_dir(path, opts, counts, progress, out)? {
+        if !opts.compact {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}:", header_name(&subdir, opts))?;
+        list_recursive(&subdir, opts, counts, progress, out)?;
+    }
+    Ok(())
+}
+
+/// Whether `list_dir` prints each entry as it is read rather than sorting
+/// the listing first: only for plain `-1` output (the `-i` column is padded
+/// to the widest inode, so it needs every entry first), and only where the
+/// order would not be changed anyway unless `--stream` says not to care.
+fn streams(opts: &Options) -> bool {
+    let plain = opts.one_per_line
+        && !opts.long
+        && !opts.long0
+        && !opts.json
+        && !opts.csv
+        && !opts.summary_json
+        && !opts.inode
+        && opts.page_size.is_none();
+    plain && (opts.stream || (opts.sort_key == SortKey::None && !opts.reverse && !opts.hard_links))
+}
+
+/// Gives the entries that share an inode a common `link_group`, numbered
+/// in listing order.  Entries whose inode appears only once keep `None`.
+fn number_hard_links(entries: &mut [EntryInfo]) {
+    let mut seen: HashMap<(u64, u64), usize> = HashMap::new();
+    for info in entries.iter() {
+        if let Some(key) = dev_ino(&info.metadata) {
+            *seen.entry(key).or_insert(0) += 1;
+        }
+    }
+    let mut groups: HashMap<(u64, u64), usize> = HashMap::new();
+    for info in entries.iter_mut() {
+        let Some(key) = dev_ino(&info.metadata) else { continue };
+        if seen[&key] < 2 || info.metadata.is_dir() {
+            continue;
+        }
+        let next = groups.len() + 1;
+        info.link_group = Some(*groups.entry(key).or_insert(next));
+    }
+}
+
+/// The `-i` column for an entry: its inode number, or `-` where there is
+/// none to show.
+fn inode_label(metadata: &Metadata) -> String {
+    dev_ino(metadata).map_or_else(|| "-".to_string(), |(_, ino)| ino.to_string())
+}
+
+/// `inode_label` padded to `width`, in the inode colour with
+/// `--colorful-columns`.
+fn inode_column(metadata: &Metadata, width: usize, opts: &Options) -> String {
+    let label = pad_left(&inode_label(metadata), width);
+    if opts.colorful_columns {
+        format!("{}{}{}", opts.color_scheme.inode, label, opts.color_scheme.reset)
+    } else {
+        label
+    }
+}
+
+/// Device and inode pair identifying the file behind an entry (unix only).
+fn dev_ino(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Mount points listed in /proc/self/mountinfo, each with the major and
+/// minor number of the device mounted there, in mount order.
+#[cfg(target_os = "linux")]
+fn read_mounts() -> Vec<((u32, u32), PathBuf)> {
+    let data = match fs::read_to_string("/proc/self/mountinfo") {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    data.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let (major, minor) = fields.nth(2)?.split_once(':')?;
+            let point = fields.nth(1)?;
+            Some(((major.parse().ok()?, minor.parse().ok()?), unescape_mountinfo(point)))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_mounts() -> Vec<((u32, u32), PathBuf)> {
+    Vec::new()
+}
+
+/// Undoes the `\040`-style octal escapes mountinfo uses for blanks and
+/// backslashes in mount points.
+#[cfg(target_os = "linux")]
+fn unescape_mountinfo(field: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match octal {
+            Some(digits) if bytes[i] == b'\\' => {
+                let value = digits.iter().fold(0_u32, |acc, d| acc * 8 + u32::from(d - b'0'));
+                out.push(value as u8);
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(out))
+}
+
+/// What the `--show-device` column says for the entry at `path`: the
+/// mount point it lives under when `mounts` knows its device, otherwise
+/// the raw device number.  Of several mounts of one device (bind mounts)
+/// the deepest one containing `path` wins.
+fn device_label(metadata: &Metadata, path: &Path, mounts: &[((u32, u32), PathBuf)]) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let dev = metadata.dev();
+        #[cfg(target_os = "linux")]
+        {
+            let id = (libc::major(dev), libc::minor(dev));
+            let candidates: Vec<&PathBuf> = mounts.iter().filter(|(m, _)| *m == id).map(|(_, p)| p).collect();
+            let best = candidates
+                .iter()
+                .filter(|p| path.starts_with(p))
+                .max_by_key(|p| p.components().count())
+                .or(candidates.last());
+            if let Some(point) = best {
+                return point.display().to_string();
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (path, mounts);
+        dev.to_string()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (metadata, path, mounts);
+        "-".to_string()
+    }
+}
+
+fn inode_number(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// Compares names with runs of digits taken as integers, so `img2` sorts
+/// before `img10`.  Equal numbers with different leading zeros put the
+/// shorter run first (`file8` before `file08`).  Callers pass lowercased
+/// names to keep the comparison case-insensitive.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(ac), Some(bc)) if ac.is_ascii_digit() && bc.is_ascii_digit() => {
+                let mut a_run = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    a_run.push(c);
+                }
+                let mut b_run = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    b_run.push(c);
+                }
+                let a_digits = a_run.trim_start_matches('0');
+                let b_digits = b_run.trim_start_matches('0');
+                let ord = a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits))
+                    .then_with(|| a_run.len().cmp(&b_run.len()));
+                if ord != std::cmp::Ordering::Equal {
+                    return ord;
+                }
             }
-            
-            let size = info.metadata.len();
-            let size_str = format_size(size, opts.human_readable);
-            size_w = size_w.max(size_str.len());
-        }
-        
-        for info in entries {
-            print_long_entry(info, link_w, uid_w, gid_w, size_w, opts);
-        }
-    } else {
-        let mut display_strings: Vec<String> = Vec::new();
-        let mut max_len = 0;
-        
-        for info in &entries {
-            let s = build_short_display(info, opts);
-            max_len = max_len.max(visible_len(&s));
-            display_strings.push(s);
-        }
-        
-        let term_width: usize = match env::var("COLUMNS") {
-            Ok(val) => val.parse().unwrap_or(80),
-            Err(_) => 80,
-        };
-        
-        let col_width = max_len + 2;
-        let cols = if opts.one_per_line {
-            1
-        } else if col_width == 0 {
-            1
-        } else {
-            let c = term_width / col_width;
-            if c == 0 { 1 } else { c }
-        };
-        
-        let rows = (display_strings.len() + cols - 1) / cols;
-        for r in 0..rows {
-            let mut line = String::new();
-            for c in 0..cols {
-                let idx = r + c * rows;
-                if idx < display_strings.len() {
-                    let s = &display_strings[idx];
-                    let vis_len = visible_len(s);
-                    line.push_str(s);
-                    if c + 1 < cols {
-                        let pad = col_width - vis_len;
-                        for _ in 0..pad {
-                            line.push(' ');
-                        }
-                    }
+            (Some(ac), Some(bc)) => {
+                if ac != bc {
+                    return ac.cmp(&bc);
                 }
+                a_chars.next();
+                b_chars.next();
             }
-            println!("{}", line);
         }
     }
 }
 
-fn build_short_display(info: &EntryInfo, opts: &Options) -> String {
-    let scheme = opts.color_scheme;
-    let file_type = info.metadata.file_type();
-    let mut parts = String::new();
-    
-    match info.git_state {
-        GitState::Added => {
-            parts.push_str(scheme.git_new);
-            parts.push('A');
-            parts.push_str(scheme.reset);
-        }
-        GitState::Modified => {
-            parts.push_str(scheme.git_modified);
-            parts.push('M');
-            parts.push_str(scheme.reset);
-        }
-        GitState::Deleted => {
-            parts.push_str(scheme.git_deleted);
-            parts.push('D');
-            parts.push_str(scheme.reset);
-        }
-        GitState::Renamed => {
-            parts.push_str(scheme.git_renamed);
-            parts.push('R');
-            parts.push_str(scheme.reset);
+/// Orders two entries once any directory/file grouping has been decided:
+/// dotfile placement first, then the `--sort` key, then name.
+/// The result is total, so repeated runs always print the same order.
+fn compare_entries(a: &EntryInfo, b: &EntryInfo, opts: &Options) -> std::cmp::Ordering {
+    let a_name = a.name.to_string_lossy().to_lowercase();
+    let b_name = b.name.to_string_lossy().to_lowercase();
+
+    let key_ord = match opts.sort_key {
+        SortKey::Name | SortKey::None => std::cmp::Ordering::Equal,
+        SortKey::Time => {
+            let a_time = entry_time(a, opts);
+            let b_time = entry_time(b, opts);
+            b_time.cmp(&a_time)
         }
-        GitState::TypeChanged => {
-            parts.push_str(scheme.git_renamed);
-            parts.push('T');
-            parts.push_str(scheme.reset);
+        SortKey::Size => entry_size(b).cmp(&entry_size(a)),
+        SortKey::Extension => {
+            let a_ext = a.path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            let b_ext = b.path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            a_ext.cmp(&b_ext)
         }
-        GitState::Untracked => {
-            parts.push_str(scheme.git_untracked);
-            parts.push('?');
-            parts.push_str(scheme.reset);
+        SortKey::Version => natural_cmp(&a_name, &b_name),
+        SortKey::Git => git_priority(a.git_state).cmp(&git_priority(b.git_state)),
+        SortKey::Length => {
+            visible_len(&a.name.to_string_lossy()).cmp(&visible_len(&b.name.to_string_lossy()))
         }
-        GitState::Ignored => {
-            parts.push_str(scheme.git_ignored);
-            parts.push('I');
-            parts.push_str(scheme.reset);
+    };
+    if key_ord != std::cmp::Ordering::Equal {
+        return key_ord;
+    }
+    
+    if opts.dotfiles == DotfilesPosition::Mixed {
+        let a_base = a_name.trim_start_matches('.');
+        let b_base = b_name.trim_start_matches('.');
+        match a_base.cmp(b_base) {
+            std::cmp::Ordering::Equal => {}
+            ord => return ord,
         }
-        GitState::None => {
-            parts.push(' ');
+    }
+    // Names that only differ in case fall back to their raw bytes, and the
+    // inode settles anything left so the order never depends on read_dir.
+    a_name
+        .cmp(&b_name)
+        .then_with(|| a.name.cmp(&b.name))
+        .then_with(|| inode_number(&a.metadata).cmp(&inode_number(&b.metadata)))
+}
+
+/// Joins a symlink's target onto the directory holding the link and folds
+/// away `.` and `..` without touching the filesystem, so dangling targets
+/// resolve just as well as live ones.
+fn resolve_link_target(link: &Path, target: &Path) -> PathBuf {
+    let joined = match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target.to_path_buf(),
+    };
+    let absolute = std::path::absolute(&joined).unwrap_or(joined);
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
         }
     }
-    parts.push(' ');
-    
-    let icon_color = if file_type.is_dir() {
+    normalized
+}
+
+fn icon_for(kind: symbols::Kind, opts: &Options) -> &'static str {
+    match opts.icon_set {
+        IconSet::Emoji => symbols::emoji_icon(kind),
+        IconSet::Nerd => symbols::nerd_icon(kind),
+        IconSet::Ascii => symbols::ascii_icon(kind),
+    }
+}
+
+fn entry_color(info: &EntryInfo, opts: &Options) -> &'static str {
+    let scheme = opts.color_scheme;
+    let file_type = info.metadata.file_type();
+    if file_type.is_dir() {
         scheme.dir
     } else if file_type.is_symlink() {
-        if fs::read_link(info.entry.path()).map_or(true, |tgt| tgt.exists()) {
+        if opts.broken_links == BrokenLinks::Plain || !is_broken_symlink(&info.path) {
             scheme.symlink
         } else {
             scheme.broken_symlink
@@ -766,7 +4139,7 @@ fn build_short_display(info: &EntryInfo, opts: &Options) -> String {
             } else if is_executable(&info.metadata) {
                 scheme.executable
             } else {
-                scheme.file
+                regular_file_color(info, opts)
             }
         }
         #[cfg(not(unix))]
@@ -774,141 +4147,782 @@ fn build_short_display(info: &EntryInfo, opts: &Options) -> String {
             if is_executable(&info.metadata) {
                 scheme.executable
             } else {
-                scheme.file
+                regular_file_color(info, opts)
+            }
+        }
+    }
+}
+
+fn regular_file_color(info: &EntryInfo, opts: &Options) -> &'static str {
+    let scheme = opts.color_scheme;
+    if opts.classify_mime {
+        if let Some(mime) = mime::guess(&info.path) {
+            return scheme.mime_color(mime::category(mime));
+        }
+    }
+    scheme.file
+}
+
+/// Whether `name` survives `--match` and `--ignore`.  Directories in a
+/// tree only answer to `--ignore`, or nothing below them could match.
+fn passes_globs(name: &str, is_dir: bool, opts: &Options) -> bool {
+    if opts.ignore_patterns.iter().any(|p| glob_match(p, name)) {
+        return false;
+    }
+    if opts.match_patterns.is_empty() || (is_dir && opts.tree_depth.is_some()) {
+        return true;
+    }
+    opts.match_patterns.iter().any(|p| glob_match(p, name))
+}
+
+/// Shell-style match of the whole of `name` against `pattern`: `*` stands
+/// for any run of characters, `?` for one, and `[abc]`, `[a-z]` or `[!abc]`
+/// for one out of (or not in) a set.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Where the last `*` was and how much of the name it has taken so far.
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() {
+            match p[pi] {
+                '*' => {
+                    star = Some((pi, ni));
+                    pi += 1;
+                    continue;
+                }
+                '?' => {
+                    pi += 1;
+                    ni += 1;
+                    continue;
+                }
+                '[' => match glob_class(&p[pi..], n[ni]) {
+                    Some((true, len)) => {
+                        pi += len;
+                        ni += 1;
+                        continue;
+                    }
+                    Some((false, _)) => {}
+                    // An unclosed `[` is just a bracket.
+                    None if n[ni] == '[' => {
+                        pi += 1;
+                        ni += 1;
+                        continue;
+                    }
+                    None => {}
+                },
+                c if c == n[ni] => {
+                    pi += 1;
+                    ni += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        match star {
+            Some((sp, sn)) => {
+                pi = sp + 1;
+                ni = sn + 1;
+                star = Some((sp, sn + 1));
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the `[...]` set at the start of `p`, returning
+/// whether it matched and how long the set is, or `None` if it is never
+/// closed.
+fn glob_class(p: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(p.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let first = i;
+    let mut matched = false;
+    while let Some(&lo) = p.get(i) {
+        if lo == ']' && i > first {
+            return Some((matched != negate, i + 1));
+        }
+        match p.get(i + 2) {
+            Some(&hi) if p[i + 1] == '-' && hi != ']' => {
+                matched |= (lo..=hi).contains(&c);
+                i += 3;
+            }
+            _ => {
+                matched |= lo == c;
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+/// A path as shown in a listing: with `--ascii`, every byte outside
+/// printable ASCII is escaped.
+fn display_path(path: &Path, opts: &Options) -> String {
+    if opts.ascii {
+        escape_non_utf8(path)
+    } else {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+/// Shows leading and trailing spaces of a name as `marker` so they cannot
+/// go unnoticed.  Spaces inside the name are left alone.
+fn mark_edge_spaces(name: &str, marker: &str) -> String {
+    let trimmed = name.trim_matches(' ');
+    if trimmed.len() == name.len() {
+        return name.to_string();
+    }
+    let leading = name.len() - name.trim_start_matches(' ').len();
+    let trailing = if trimmed.is_empty() { 0 } else { name.len() - name.trim_end_matches(' ').len() };
+    let mut marked = marker.repeat(leading);
+    marked.push_str(trimmed);
+    marked.push_str(&marker.repeat(trailing));
+    marked
+}
+
+/// Appends `name`, wrapping every occurrence of a `--highlight` pattern in
+/// the highlight colour and returning to `name_color` afterwards.
+fn push_highlighted(parts: &mut String, name: &str, name_color: &str, opts: &Options) {
+    let scheme = opts.color_scheme;
+    let mut marked = vec![false; name.len()];
+    for pattern in opts.highlight_patterns.iter().filter(|p| !p.is_empty()) {
+        for (start, matched) in name.match_indices(pattern.as_str()) {
+            for flag in &mut marked[start..start + matched.len()] {
+                *flag = true;
+            }
+        }
+    }
+    let mut in_highlight = false;
+    for (i, c) in name.char_indices() {
+        if marked[i] != in_highlight {
+            in_highlight = marked[i];
+            if in_highlight {
+                parts.push_str(scheme.highlight);
+            } else {
+                parts.push_str(scheme.reset);
+                parts.push_str(name_color);
             }
         }
+        parts.push(c);
+    }
+    if in_highlight {
+        parts.push_str(scheme.reset);
+        parts.push_str(name_color);
+    }
+}
+
+fn build_short_display(info: &EntryInfo, opts: &Options) -> String {
+    let scheme = opts.color_scheme;
+    let file_type = info.metadata.file_type();
+    let mut parts = String::new();
+    
+    if opts.git_position == GitPosition::Leading {
+        parts.push_str(&git_marker(info, opts));
+        parts.push(' ');
+    }
+    
+    let icon_color = entry_color(info, opts);
+    
+    if opts.icon_mode != IconMode::Never {
+        parts.push_str(icon_color);
+        parts.push_str(info.icon);
+        parts.push_str(scheme.reset);
+        parts.push(' ');
+    }
+    
+    let name_color = match (opts.name_color, info.git_state) {
+        (NameColor::Type, _) => icon_color,
+        (NameColor::Git, state) => git_color(state, &scheme).unwrap_or(icon_color),
+        (NameColor::GitOverModified, state @ (GitState::Modified | GitState::Conflicted)) => {
+            git_color(state, &scheme).unwrap_or(icon_color)
+        }
+        (NameColor::GitOverModified, _) => icon_color,
     };
     
-    parts.push_str(icon_color);
-    parts.push_str(info.icon);
+    let mut file_name_str = display_path(Path::new(&info.name), opts);
+    if opts.mark_spaces {
+        let marker = if opts.ascii { "\\x20" } else { "␣" };
+        file_name_str = mark_edge_spaces(&file_name_str, marker);
+    }
+    parts.push_str(name_color);
+    push_highlighted(&mut parts, &file_name_str, name_color, opts);
+    if opts.classify {
+        if let Some(indicator) = classify_indicator(&info.metadata) {
+            parts.push_str(scheme.reset);
+            parts.push(indicator);
+        }
+    }
+    
+    if file_type.is_symlink() {
+        if let Ok(target) = fs::read_link(&info.path) {
+            parts.push_str(scheme.reset);
+            parts.push_str(" -> ");
+            let target = if opts.resolve_links {
+                resolve_link_target(&info.path, &target)
+            } else {
+                target
+            };
+            let target_str = display_path(&target, opts);
+            let target_color = if is_broken_symlink(&info.path) {
+                if opts.broken_links == BrokenLinks::Highlight { scheme.broken_target } else { name_color }
+            } else {
+                link_target_color(&info.path, &scheme)
+            };
+            parts.push_str(target_color);
+            parts.push_str(&target_str);
+        }
+    }
     parts.push_str(scheme.reset);
-    parts.push(' ');
     
-    let name_color = if file_type.is_dir() {
-        scheme.dir
-    } else if file_type.is_symlink() {
-        if fs::read_link(info.entry.path()).map_or(true, |tgt| tgt.exists()) {
-            scheme.symlink
-        } else {
-            scheme.broken_symlink
+    if let Some(from) = &info.renamed_from {
+        // A rename within the same directory only needs the old file name.
+        let same_dir = from.parent().is_none_or(|p| info.path.ends_with(p.join(&info.name)));
+        let shown = match from.file_name() {
+            Some(old_name) if same_dir => Path::new(old_name),
+            _ => from.as_path(),
+        };
+        parts.push(' ');
+        parts.push_str(scheme.rename_source);
+        parts.push_str(&format!("(was {})", display_path(shown, opts)));
+        parts.push_str(scheme.reset);
+    }
+    
+    if info.is_new {
+        parts.push(' ');
+        parts.push_str(scheme.new_badge);
+        parts.push_str("[new]");
+        parts.push_str(scheme.reset);
+    }
+    
+    if info.hidden_links > 0 {
+        parts.push(' ');
+        parts.push_str(scheme.rename_source);
+        parts.push_str(&format!("(+{} link{})", info.hidden_links, if info.hidden_links == 1 { "" } else { "s" }));
+        parts.push_str(scheme.reset);
+    }
+    
+    if let Some(group) = info.link_group {
+        parts.push(' ');
+        parts.push_str(scheme.rename_source);
+        parts.push_str(&format!("[link {}]", group));
+        parts.push_str(scheme.reset);
+    }
+    
+    if opts.git_position == GitPosition::Trailing && info.git_state != GitState::None {
+        parts.push(' ');
+        parts.push_str(&git_marker(info, opts));
+    }
+    parts
+}
+
+/// The `-F` character for an entry's type, if it gets one.
+fn classify_indicator(metadata: &Metadata) -> Option<char> {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        return Some('/');
+    }
+    if file_type.is_symlink() {
+        return Some('@');
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some('|');
         }
-    } else {
+        if file_type.is_socket() {
+            return Some('=');
+        }
+    }
+    if file_type.is_file() && is_executable(metadata) {
+        return Some('*');
+    }
+    None
+}
+
+/// Rank for `--sort=git`: conflicts first, then local changes, untracked
+/// files, clean entries and finally ignored ones.
+fn git_priority(state: GitState) -> u8 {
+    match state {
+        GitState::Conflicted => 0,
+        GitState::Modified => 1,
+        GitState::Deleted => 2,
+        GitState::Renamed | GitState::TypeChanged => 3,
+        GitState::Added => 4,
+        GitState::Untracked => 5,
+        GitState::None => 6,
+        GitState::Ignored => 7,
+    }
+}
+
+fn git_color(state: GitState, scheme: &ColorScheme) -> Option<&'static str> {
+    match state {
+        GitState::Added => Some(scheme.git_new),
+        GitState::Modified => Some(scheme.git_modified),
+        GitState::Deleted => Some(scheme.git_deleted),
+        GitState::Renamed | GitState::TypeChanged => Some(scheme.git_renamed),
+        GitState::Untracked => Some(scheme.git_untracked),
+        GitState::Ignored => Some(scheme.git_ignored),
+        GitState::Conflicted => Some(scheme.git_conflicted),
+        GitState::None => None,
+    }
+}
+
+fn git_indicator(state: GitState, scheme: &ColorScheme) -> String {
+    match git_color(state, scheme) {
+        Some(color) => format!("{}{}{}", color, git_letter(state), scheme.reset),
+        None => " ".to_string(),
+    }
+}
+
+/// The git column of an entry: one letter, or with `--git-columns` the
+/// staged and unstaged letters side by side like `git status --short`.
+fn git_marker(info: &EntryInfo, opts: &Options) -> String {
+    let scheme = &opts.color_scheme;
+    if !opts.git_columns {
+        return git_indicator(info.git_state, scheme);
+    }
+    let column = |state: GitState, color: &'static str| match state {
+        GitState::None => " ".to_string(),
+        GitState::Untracked | GitState::Ignored | GitState::Conflicted => git_indicator(state, scheme),
+        state => format!("{}{}{}", color, git_letter(state), scheme.reset),
+    };
+    let flags = info.git_flags;
+    format!("{}{}", column(flags.index, scheme.git_staged), column(flags.worktree, scheme.git_unstaged))
+}
+
+fn git_letter(state: GitState) -> &'static str {
+    match state {
+        GitState::Added => "A",
+        GitState::Modified => "M",
+        GitState::Deleted => "D",
+        GitState::Renamed => "R",
+        GitState::TypeChanged => "T",
+        GitState::Untracked => "?",
+        GitState::Ignored => "I",
+        GitState::Conflicted => "U",
+        GitState::None => "",
+    }
+}
+
+/// Writes one `--long0` record.  The name is written as raw bytes so that
+/// every file name survives the trip to the consuming script.
+fn write_long0_record(out: &mut dyn Write, info: &EntryInfo) -> io::Result<()> {
+    let md = &info.metadata;
+    #[cfg(unix)]
+    let (links, uid, gid) = {
+        use std::os::unix::fs::MetadataExt;
+        (md.nlink(), md.uid(), md.gid())
+    };
+    #[cfg(not(unix))]
+    let (links, uid, gid) = (1_u64, 0_u32, 0_u32);
+    let mtime = md
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    
+    let fields = [
+        perm_string(&md.file_type(), md),
+        links.to_string(),
+        uid.to_string(),
+        gid.to_string(),
+        entry_size(info).to_string(),
+        mtime.to_string(),
+        git_letter(info.git_state).to_string(),
+    ];
+    for field in &fields {
+        out.write_all(field.as_bytes())?;
+        out.write_all(b"\0")?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        out.write_all(info.name.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    out.write_all(info.name.to_string_lossy().as_bytes())?;
+    out.write_all(b"\n")
+}
+
+fn print_long_entry(info: EntryInfo, layout: &LongLayout, opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    let scheme = opts.color_scheme;
+    let file_type = info.metadata.file_type();
+    let perm = perm_string(&file_type, &info.metadata);
+    
+    let links: u64 = {
         #[cfg(unix)]
         {
-            use std::os::unix::fs::FileTypeExt;
-            if file_type.is_fifo() {
-                scheme.pipe
-            } else if file_type.is_socket() {
-                scheme.socket
-            } else if file_type.is_block_device() {
-                scheme.block_device
-            } else if file_type.is_char_device() {
-                scheme.char_device
-            } else if is_executable(&info.metadata) {
-                scheme.executable
-            } else {
-                scheme.file
-            }
+            use std::os::unix::fs::MetadataExt;
+            info.metadata.nlink()
         }
         #[cfg(not(unix))]
         {
-            if is_executable(&info.metadata) {
-                scheme.executable
-            } else {
-                scheme.file
-            }
+            1
+        }
+    };
+    
+    #[cfg(unix)]
+    let (uid_num, gid_num) = {
+        use std::os::unix::fs::MetadataExt;
+        (info.metadata.uid(), info.metadata.gid())
+    };
+    #[cfg(not(unix))]
+    let (uid_num, gid_num) = (0_u32, 0_u32);
+    
+    let uid_str = layout.user(uid_num);
+    let gid_str = layout.group(gid_num);
+    
+    let size = entry_size(&info);
+    let size_str = if opts.bytes_aligned {
+        let (number, unit) = layout.size_parts(size, opts);
+        if layout.size_unit_w == 0 {
+            pad_left(&number, layout.size_w)
+        } else {
+            format!("{} {}", pad_left(&number, layout.size_w), pad_right(unit, layout.size_unit_w))
+        }
+    } else {
+        pad_left(&layout.format_size(size, opts), layout.size_w)
+    };
+    
+    let time_str = format_time(entry_time(&info, opts), opts);
+    
+    let git_ch = git_marker(&info, opts);
+    
+    let short = build_short_display(&info, opts);
+    
+    let (links_str, size_str) = if opts.colorful_columns {
+        (
+            format!("{}{}{}", scheme.links, links, scheme.reset),
+            format!("{}{}{}", scheme.size_color(size, if opts.si { 1000 } else { 1024 }), size_str, scheme.reset),
+        )
+    } else {
+        (links.to_string(), size_str)
+    };
+    
+    if opts.inode {
+        write!(out, "{} ", inode_column(&info.metadata, layout.inode_w, opts))?;
+    }
+    #[cfg(unix)]
+    if opts.blocks {
+        let blocks = format_size(allocated_size(&info.metadata), opts.human_readable, opts.si);
+        write!(out, "{} ", pad_left(&blocks, layout.blocks_w))?;
+    }
+    if opts.octal_perms {
+        write!(out, "{} ", octal_mode(&info.metadata))?;
+    }
+    write!(out, "{}{} ", perm, xattr_marker(&info.path))?;
+    write!(out, "{} ", pad_left(&links_str, layout.link_w))?;
+    // Names line up on the left like ls(1) does; bare numbers on the right.
+    if opts.numeric_ids {
+        write!(out, " {} ", pad_left(&uid_str, layout.uid_w))?;
+        write!(out, " {} ", pad_left(&gid_str, layout.gid_w))?;
+    } else {
+        write!(out, " {} ", pad_right(&uid_str, layout.uid_w))?;
+        write!(out, " {} ", pad_right(&gid_str, layout.gid_w))?;
+    }
+    if let Some(devices) = &layout.devices {
+        let device = devices.get(&info.path).map_or("-", |d| d.as_str());
+        write!(out, " {} ", pad_right(device, layout.device_w))?;
+    }
+    write!(out, " {} ", size_str)?;
+    write!(out, " {} ", time_str)?;
+    if let Some(authors) = &layout.blame {
+        let author = authors.get(&info.path).map_or("-", |a| a.as_str());
+        write!(out, "{}{}{} ", scheme.blame, pad_right(author, layout.blame_w), scheme.reset)?;
+    }
+    write!(out, "{} ", git_ch)?;
+    writeln!(out, "{}", short)?;
+    Ok(())
+}
+
+fn type_name(file_type: &FileType, path: &Path) -> &'static str {
+    if file_type.is_dir() {
+        return "directory";
+    }
+    if file_type.is_symlink() {
+        if fs::metadata(path).is_ok() {
+            return "symbolic link";
         }
+        return "broken symbolic link";
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return "fifo";
+        } else if file_type.is_socket() {
+            return "socket";
+        } else if file_type.is_block_device() {
+            return "block device";
+        } else if file_type.is_char_device() {
+            return "character device";
+        }
+    }
+    "regular file"
+}
+
+/// Birth time of `path` itself (symlinks are not followed).  On Linux this
+/// asks statx(2) for STATX_BTIME directly, since not every filesystem or
+/// libc hands it to `Metadata::created`.  `None` if the filesystem does not
+/// record it.
+#[cfg(target_os = "linux")]
+fn btime(path: &Path) -> Option<SystemTime> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stx: libc::statx = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and statx only writes the struct
+    // we pass.
+    let ret = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+            libc::STATX_BTIME,
+            &mut stx,
+        )
     };
-    
-    let file_name = info.entry.file_name();
-    let file_name_str = file_name.to_string_lossy();
-    parts.push_str(name_color);
-    parts.push_str(&file_name_str);
-    
-    if file_type.is_symlink() {
-        match fs::read_link(info.entry.path()) {
-            Ok(target) => {
-                parts.push_str(scheme.reset);
-                parts.push_str(" -> ");
-                let target_str = target.to_string_lossy();
-                parts.push_str(name_color);
-                parts.push_str(&target_str);
-            }
-            Err(_) => {}
+    if ret != 0 || stx.stx_mask & libc::STATX_BTIME == 0 {
+        return None;
+    }
+    let secs = u64::try_from(stx.stx_btime.tv_sec).ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, stx.stx_btime.tv_nsec))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn btime(path: &Path) -> Option<SystemTime> {
+    fs::symlink_metadata(path).ok()?.created().ok()
+}
+
+/// The timestamp `--time` picked for an entry, falling back to the
+/// modification time where the platform or filesystem does not have it.
+fn entry_time(info: &EntryInfo, opts: &Options) -> SystemTime {
+    let time = match opts.time_field {
+        TimeField::Modified => None,
+        TimeField::Accessed => info.metadata.accessed().ok(),
+        TimeField::Changed => changed_time(&info.metadata),
+        TimeField::Birth => btime(&info.path),
+    };
+    time.or_else(|| info.metadata.modified().ok())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// The size shown for an entry: its own length, or with `--total-size`
+/// what a directory holds.
+fn entry_size(info: &EntryInfo) -> u64 {
+    info.total_size.unwrap_or(info.metadata.len())
+}
+
+/// Sums the sizes of the regular files anywhere below `path`.  Symlinks
+/// are not followed, so a link loop cannot send the walk in circles.  Every
+/// entry walked counts towards `--progress`.
+fn dir_total_size(path: &Path, progress: &mut Progress) -> u64 {
+    let read_dir = match fs::read_dir(path) {
+        Ok(rd) => rd,
+        Err(_) => return 0,
+    };
+    let mut total = 0;
+    for entry in read_dir.flatten() {
+        progress.tick();
+        let metadata = match fs::symlink_metadata(entry.path()) {
+            Ok(md) => md,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            total += dir_total_size(&entry.path(), progress);
+        } else if metadata.is_file() {
+            total += metadata.len();
         }
     }
-    parts.push_str(scheme.reset);
-    parts
+    total
+}
+
+/// Last status change time, which std only exposes through `MetadataExt`.
+fn changed_time(metadata: &Metadata) -> Option<SystemTime> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let secs = u64::try_from(metadata.ctime()).ok()?;
+        Some(UNIX_EPOCH + Duration::new(secs, metadata.ctime_nsec() as u32))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
 }
 
-fn print_long_entry(info: EntryInfo, link_w: usize, uid_w: usize, gid_w: usize, size_w: usize, opts: &Options) {
+fn print_stat(path: &Path, opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(md) => md,
+        Err(e) => {
+            eprintln!("rdir: cannot access {}: {}", path.display(), e);
+            return Ok(());
+        }
+    };
     let scheme = opts.color_scheme;
-    let file_type = info.metadata.file_type();
-    let perm = perm_string(&file_type, &info.metadata);
+    let file_type = metadata.file_type();
+    let full_path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name().map_or_else(|| full_path.clone().into_os_string(), |n| n.to_owned());
+    
+    let parent = parent_dir(path);
+    // Git statuses are keyed by canonical path, as in read_entries.
+    let git_state = if opts.git_status {
+        let key = fs::canonicalize(&parent).unwrap_or_default().join(&name);
+        load_git_statuses(&parent, opts).get(&key).map_or(GitState::None, |git| git.state)
+    } else {
+        GitState::None
+    };
+    
+    // The git state gets its own line, so keep it out of the name display.
+    let info = EntryInfo {
+        path: path.to_path_buf(),
+        name,
+        icon: icon_for(symbols::get_file_kind(&file_type, path), opts),
+        metadata,
+        git_state: GitState::None,
+        git_flags: GitFlags::CLEAN,
+        renamed_from: None,
+        hidden_links: 0,
+        link_group: None,
+        is_new: false,
+        total_size: None,
+    };
+    let display = build_short_display(&info, opts);
+    let md = &info.metadata;
+    
+    #[cfg(unix)]
+    let (mode, uid, gid) = {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        (md.permissions().mode() & 0o7777, md.uid(), md.gid())
+    };
+    #[cfg(not(unix))]
+    let (mode, uid, gid) = (0_u32, 0_u32, 0_u32);
     
     let links: u64 = {
         #[cfg(unix)]
         {
             use std::os::unix::fs::MetadataExt;
-            info.metadata.nlink() as u64
+            md.nlink()
         }
         #[cfg(not(unix))]
         {
             1
         }
     };
-    
-    #[cfg(unix)]
-    let (uid_num, gid_num) = {
-        use std::os::unix::fs::MetadataExt;
-        (info.metadata.uid(), info.metadata.gid())
+    let time_or_dash = |t: Option<SystemTime>| t.map_or_else(|| "-".to_string(), |t| format_time(t, opts));
+    let id_with_name = |name: Option<String>, id: u32| match name {
+        Some(name) if !opts.numeric_ids => format!("{} ({})", name, id),
+        _ => id.to_string(),
     };
-    #[cfg(not(unix))]
-    let (uid_num, gid_num) = (0_u32, 0_u32);
-    
-    let uid_str = format!("{}", uid_num);
-    let gid_str = format!("{}", gid_num);
-    
-    let size = info.metadata.len();
-    let size_str = format_size(size, opts.human_readable);
-    
-    let mtime = info.metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-    let time_str = format_time(mtime);
-    
-    let git_ch = match info.git_state {
-        GitState::Added => {
-            format!("{}A{}", scheme.git_new, scheme.reset)
-        }
-        GitState::Modified => {
-            format!("{}M{}", scheme.git_modified, scheme.reset)
-        }
-        GitState::Deleted => {
-            format!("{}D{}", scheme.git_deleted, scheme.reset)
-        }
-        GitState::Renamed => {
-            format!("{}R{}", scheme.git_renamed, scheme.reset)
-        }
-        GitState::TypeChanged => {
-            format!("{}T{}", scheme.git_renamed, scheme.reset)
-        }
-        GitState::Untracked => {
-            format!("{}?{}", scheme.git_untracked, scheme.reset)
-        }
-        GitState::Ignored => {
-            format!("{}I{}", scheme.git_ignored, scheme.reset)
-        }
-        GitState::None => " ".to_string(),
+    let git_str = match git_state {
+        GitState::None => "-".to_string(),
+        state => git_indicator(state, &scheme),
     };
     
-    let short = build_short_display(&info, opts);
-    
-    print!("{} ", perm);
-    print!("{:>width$} ", links, width = link_w);
-    print!(" {:>uid_w$} ", uid_str, uid_w = uid_w);
-    print!(" {:>gid_w$} ", gid_str, gid_w = gid_w);
-    print!(" {:>size_w$} ", size_str, size_w = size_w);
-    print!(" {} {} ", time_str, git_ch);
-    println!("{}", short);
+    let mut fields: Vec<(&str, String)> = vec![
+        ("Name", display.trim_start().to_string()),
+        ("Path", display_path(&full_path, opts)),
+        ("Type", type_name(&file_type, path).to_string()),
+        ("Size", format!("{} ({} bytes)", format_size(md.len(), true, opts.si), md.len())),
+        ("Permissions", format!("{} ({:04o})", perm_string(&file_type, md), mode)),
+        ("Owner", id_with_name(user_name(uid), uid)),
+        ("Group", id_with_name(group_name(gid), gid)),
+        ("Accessed", time_or_dash(md.accessed().ok())),
+        ("Modified", time_or_dash(md.modified().ok())),
+        ("Changed", time_or_dash(changed_time(md))),
+        ("Created", time_or_dash(btime(path))),
+        ("Inode", inode_number(md).to_string()),
+        ("Links", links.to_string()),
+    ];
+    if opts.git_status {
+        fields.push(("Git", git_str));
+    }
+    let key_w = fields.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (key, value) in fields {
+        writeln!(out, "{:>key_w$}: {}", key, value, key_w = key_w)?;
+    }
+    Ok(())
+}
+
+/// Directories smaller than this are stat'ed on the calling thread, where
+/// starting workers would cost more than it saves.
+const PARALLEL_STAT_MIN: usize = 1024;
+
+/// `entry_metadata` for each of `paths`, in the same order, split across
+/// `--threads` scoped threads when there are enough of them.
+fn stat_entries(paths: &[PathBuf], opts: &Options) -> Vec<io::Result<Metadata>> {
+    let threads = opts
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    if threads <= 1 || paths.len() < PARALLEL_STAT_MIN {
+        return paths.iter().map(|path| entry_metadata(path, opts)).collect();
+    }
+    let chunk = paths.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = paths
+            .chunks(chunk)
+            .map(|part| scope.spawn(move || part.iter().map(|path| entry_metadata(path, opts)).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Metadata of the entry at `path`: of the link itself, or with `-L` of
+/// what it points at.  A link whose target is missing stays a link.
+fn entry_metadata(path: &Path, opts: &Options) -> io::Result<Metadata> {
+    let md = fs::symlink_metadata(path)?;
+    if opts.dereference && md.file_type().is_symlink() {
+        return Ok(fs::metadata(path).unwrap_or(md));
+    }
+    Ok(md)
+}
+
+/// Whether a tree walk should go into `info`: a directory, unless it is a
+/// followed (`-L`) link back to a directory the walk is already inside.
+fn enters(info: &EntryInfo) -> bool {
+    if !info.metadata.is_dir() {
+        return false;
+    }
+    if !fs::symlink_metadata(&info.path).is_ok_and(|md| md.file_type().is_symlink()) {
+        return true;
+    }
+    match (fs::canonicalize(&info.path), info.path.parent().map(fs::canonicalize)) {
+        (Ok(target), Some(Ok(parent))) => !parent.starts_with(target),
+        _ => false,
+    }
+}
+
+/// A link is broken when following it all the way leads nowhere.  Going
+/// through the filesystem resolves relative targets against the link's own
+/// directory rather than the current one.
+fn is_broken_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|md| md.file_type().is_symlink()) && fs::metadata(path).is_err()
+}
+
+/// Colour for the `-> target` part of a working link, after the type of
+/// what it names.  Only one hop is taken, so a link to a link gets the
+/// symlink colour.
+fn link_target_color(link: &Path, scheme: &ColorScheme) -> &'static str {
+    let target = match fs::read_link(link) {
+        Ok(target) => link.parent().unwrap_or(Path::new("")).join(target),
+        Err(_) => return scheme.symlink,
+    };
+    match fs::symlink_metadata(&target) {
+        Ok(md) if md.file_type().is_symlink() => scheme.symlink,
+        Ok(md) if md.is_dir() => scheme.dir,
+        Ok(md) if md.is_file() && is_executable(&md) => scheme.executable,
+        _ => scheme.file,
+    }
 }
 
 fn is_executable(metadata: &Metadata) -> bool {
@@ -925,144 +4939,134 @@ fn is_executable(metadata: &Metadata) -> bool {
     }
 }
 
-fn print_tree(current: &Path, root: &Path, prefix: String, depth: usize, opts: &Options, git_map: &HashMap<PathBuf, GitState>, counts: &mut Counts) {
-    let read_dir = match fs::read_dir(current) {
-        Ok(rd) => rd,
-        Err(e) => {
-            eprintln!("rdir: cannot access {}: {}", current.display(), e);
-            return;
-        }
+/// `ancestors` holds the device and inode of every directory from the root
+/// down to `current`, so a directory that leads back to one of them (a bind
+/// mount, or a symlink with `-L`) is flagged instead of walked forever.
+#[allow(clippy::too_many_arguments)]
+fn print_tree(current: &Path, root: &Path, prefix: String, depth: usize, opts: &Options, git_map: &GitMap, ancestors: &mut HashSet<(u64, u64)>, counts: &mut Counts, progress: &mut Progress, out: &mut dyn Write) -> io::Result<()> {
+    let key = fs::metadata(current).ok().and_then(|md| dev_ino(&md));
+    if let Some(key) = key {
+        ancestors.insert(key);
+    }
+    let result = print_tree_entries(current, root, prefix, depth, opts, git_map, ancestors, counts, progress, out);
+    if let Some(key) = key {
+        ancestors.remove(&key);
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn print_tree_entries(current: &Path, root: &Path, prefix: String, depth: usize, opts: &Options, git_map: &GitMap, ancestors: &mut HashSet<(u64, u64)>, counts: &mut Counts, progress: &mut Progress, out: &mut dyn Write) -> io::Result<()> {
+    let level = current.strip_prefix(root).map_or(0, |rel| rel.components().count()) + 1;
+    let mut entries = match read_entries(current, opts, git_map, counts, progress, None) {
+        Some(entries) => entries,
+        None => return Ok(()),
     };
+    sort_entries(&mut entries, opts, opts.sort_dirs_first, opts.sort_files_first);
+    if opts.hard_links {
+        number_hard_links(&mut entries);
+    }
     
-    let mut entries: Vec<EntryInfo> = Vec::new();
-    for res in read_dir {
-        match res {
-            Ok(entry) => {
-                let file_name = entry.file_name();
-                let file_name_str = file_name.to_string_lossy();
-                
-                if !opts.all {
-                    if file_name_str.starts_with('.') {
-                        continue;
-                    }
-                }
-                
-                let metadata = match fs::symlink_metadata(entry.path()) {
-                    Ok(md) => md,
-                    Err(_) => continue,
-                };
-                
-                let file_type = metadata.file_type();
-                
-                if opts.dirs_only && !file_type.is_dir() {
-                    continue;
-                }
-                if opts.files_only && file_type.is_dir() {
-                    continue;
-                }
-                
-                let rel_path = match entry.path().strip_prefix(root) {
-                    Ok(p) => p.to_owned(),
-                    Err(_) => entry.path(),
-                };
-                let git_state = git_map.get(&rel_path).cloned().unwrap_or(GitState::None);
-                let icon = symbols::get_file_icon(&file_type, &entry.path());
-                
-                if file_type.is_dir() {
-                    counts.dirs += 1;
-                } else if file_type.is_symlink() {
-                    if fs::read_link(entry.path()).map_or(true, |tgt| tgt.exists()) {
-                        counts.symlinks += 1;
-                    } else {
-                        counts.broken_symlinks += 1;
-                    }
-                } else {
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::FileTypeExt;
-                        if file_type.is_fifo() {
-                            counts.pipes += 1;
-                        } else if file_type.is_socket() {
-                            counts.sockets += 1;
-                        } else if file_type.is_block_device() {
-                            counts.block_devices += 1;
-                        } else if file_type.is_char_device() {
-                            counts.char_devices += 1;
-                        } else {
-                            counts.files += 1;
-                        }
-                    }
-                    #[cfg(not(unix))]
-                    {
-                        counts.files += 1;
-                    }
-                }
-                
-                entries.push(EntryInfo {
-                    entry,
-                    metadata,
-                    icon,
-                    git_state,
-                });
-            }
-            Err(e) => {
-                eprintln!("rdir: error reading directory: {}", e);
-            }
+    let mut elided = Vec::new();
+    if let Some(max) = opts.max_entries_per_dir {
+        if entries.len() > max {
+            elided = entries.split_off(max);
         }
     }
     
-    entries.sort_by(|a, b| {
-        let a_dir = a.metadata.file_type().is_dir();
-        let b_dir = b.metadata.file_type().is_dir();
-        if a_dir != b_dir {
-            if a_dir { return std::cmp::Ordering::Less; }
-            else { return std::cmp::Ordering::Greater; }
-        }
-        
-        if opts.sort_time {
-            let a_time = a.metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-            let b_time = b.metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-            match b_time.cmp(&a_time) {
-                std::cmp::Ordering::Equal => {}
-                ord => return ord,
-            }
-        }
-        
-        let a_name = a.entry.file_name().to_string_lossy().to_lowercase();
-        let b_name = b.entry.file_name().to_string_lossy().to_lowercase();
-        a_name.cmp(&b_name)
-    });
+    let (branch, last, pipe, ellipsis) = if opts.ascii {
+        ("|-- ", "`-- ", "|   ", "...")
+    } else {
+        ("├── ", "└── ", "│   ", "…")
+    };
     
     let len = entries.len();
     for (i, info) in entries.into_iter().enumerate() {
-        let is_last = i == len - 1;
+        let is_last = i == len - 1 && elided.is_empty();
         
         let mut line = prefix.clone();
         if is_last {
-            line.push_str("└── ");
+            line.push_str(last);
         } else {
-            line.push_str("├── ");
+            line.push_str(branch);
+        }
+        if opts.depth_colors {
+            let scheme = opts.color_scheme;
+            line = format!("{}{}{}", scheme.depth_color(level), line, scheme.reset);
         }
         
-        let disp = build_short_display(&info, opts);
-        println!("{}{}", line, disp);
+        let cycle = info.metadata.is_dir()
+            && fs::metadata(&info.path).ok().and_then(|md| dev_ino(&md)).is_some_and(|key| ancestors.contains(&key));
+
+        if opts.csv && !opts.summary_json {
+            let rel = info.path.strip_prefix(root).unwrap_or(&info.path);
+            progress.clear();
+            writeln!(out, "{}", csv_row(&info, &display_path(rel, opts), Some(level), opts))?;
+        } else if opts.flatten && !opts.summary_json {
+            let rel = info.path.strip_prefix(root).unwrap_or(&info.path);
+            progress.clear();
+            writeln!(out, "{}", display_path(rel, opts))?;
+        } else if !opts.summary_json {
+            let mut disp = build_short_display(&info, opts);
+            if cycle {
+                disp.push_str(" [recursion detected]");
+            }
+            progress.clear();
+            writeln!(out, "{}{}", line, disp)?;
+        }
         
-        if info.metadata.file_type().is_dir() {
+        if enters(&info) && !cycle {
             let new_prefix = if is_last {
                 format!("{}    ", prefix)
             } else {
-                format!("{}│   ", prefix)
+                format!("{}{}", prefix, pipe)
             };
             if depth > 1 {
-                print_tree(&info.entry.path(), root, new_prefix, depth - 1, opts, git_map, counts);
+                print_tree(&info.path, root, new_prefix, depth - 1, opts, git_map, ancestors, counts, progress, out)?;
             } else if depth == usize::MAX {
-                print_tree(&info.entry.path(), root, new_prefix, usize::MAX, opts, git_map, counts);
+                print_tree(&info.path, root, new_prefix, usize::MAX, opts, git_map, ancestors, counts, progress, out)?;
             }
         }
     }
+    
+    if elided.is_empty() {
+        return Ok(());
+    }
+    if !opts.summary_json && !opts.csv && !opts.flatten {
+        progress.clear();
+        let (color, reset) = if opts.depth_colors {
+            (opts.color_scheme.depth_color(level), opts.color_scheme.reset)
+        } else {
+            ("", "")
+        };
+        writeln!(out, "{}{}{}{}{} ({} more)", color, prefix, last, reset, ellipsis, elided.len())?;
+    }
+    // Keep walking what was left out so the report still has true totals.
+    for info in &elided {
+        if enters(info) && depth > 1 {
+            let next = if depth == usize::MAX { depth } else { depth - 1 };
+            count_tree(&info.path, next, opts, git_map, counts, progress);
+        }
+    }
+    Ok(())
+}
+
+/// Counts the entries below `current` like `print_tree` would, without
+/// printing anything.
+fn count_tree(current: &Path, depth: usize, opts: &Options, git_map: &GitMap, counts: &mut Counts, progress: &mut Progress) {
+    let entries = match read_entries(current, opts, git_map, counts, progress, None) {
+        Some(entries) => entries,
+        None => return,
+    };
+    for info in &entries {
+        if enters(info) && depth > 1 {
+            let next = if depth == usize::MAX { depth } else { depth - 1 };
+            count_tree(&info.path, next, opts, git_map, counts, progress);
+        }
+    }
 }
 
-fn print_report(counts: &Counts) {
+fn print_report(counts: &Counts, opts: &Options, out: &mut dyn Write) -> io::Result<()> {
     let mut parts: Vec<String> = Vec::new();
     if counts.dirs > 0 {
         parts.push(format!("{} director{}", counts.dirs, if counts.dirs == 1 { "y" } else { "ies" }));
@@ -1088,7 +5092,465 @@ fn print_report(counts: &Counts) {
     if counts.char_devices > 0 {
         parts.push(format!("{} char device{}", counts.char_devices, if counts.char_devices == 1 { "" } else { "s" }));
     }
+    if counts.files > 0 {
+        parts.push(format!("{} total", format_size(counts.total_bytes, opts.human_readable, opts.si)));
+    }
+    if !parts.is_empty() {
+        if !opts.compact {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}", parts.join(", "))?;
+    }
+    Ok(())
+}
+
+/// `--tree-summary-icons`: the non-zero counts, each after the icon of its
+/// kind.  Broken symlinks are counted with the other symlinks.
+fn print_icon_summary(counts: &Counts, opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    let tally = [
+        (symbols::Kind::Directory, counts.dirs),
+        (symbols::Kind::GenericFile, counts.files),
+        (symbols::Kind::Symlink, counts.symlinks + counts.broken_symlinks),
+        (symbols::Kind::Pipe, counts.pipes),
+        (symbols::Kind::Socket, counts.sockets),
+        (symbols::Kind::BlockDevice, counts.block_devices),
+        (symbols::Kind::CharDevice, counts.char_devices),
+    ];
+    let parts: Vec<String> = tally
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|&(kind, n)| format!("{} {}", icon_for(kind, opts), n))
+        .collect();
     if !parts.is_empty() {
-        println!("\n{}", parts.join(", "));
+        if !opts.compact {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}", parts.join("  "))?;
+    }
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The `type` of an entry in `--json` output.
+fn json_type(file_type: &FileType) -> &'static str {
+    if file_type.is_dir() {
+        return "dir";
+    }
+    if file_type.is_symlink() {
+        return "symlink";
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return "pipe";
+        } else if file_type.is_socket() {
+            return "socket";
+        } else if file_type.is_block_device() {
+            return "block_device";
+        } else if file_type.is_char_device() {
+            return "char_device";
+        }
+    }
+    "file"
+}
+
+fn git_state_name(state: GitState) -> Option<&'static str> {
+    match state {
+        GitState::Added => Some("added"),
+        GitState::Modified => Some("modified"),
+        GitState::Deleted => Some("deleted"),
+        GitState::Renamed => Some("renamed"),
+        GitState::TypeChanged => Some("type_changed"),
+        GitState::Untracked => Some("untracked"),
+        GitState::Ignored => Some("ignored"),
+        GitState::Conflicted => Some("conflicted"),
+        GitState::None => None,
+    }
+}
+
+/// One `--json` object.  `children` is only given for directories that
+/// `--tree` descended into.
+fn entry_json(info: &EntryInfo, children: Option<&[String]>, opts: &Options) -> String {
+    let md = &info.metadata;
+    let file_type = md.file_type();
+    #[cfg(unix)]
+    let (links, uid, gid) = {
+        use std::os::unix::fs::MetadataExt;
+        (md.nlink(), md.uid(), md.gid())
+    };
+    #[cfg(not(unix))]
+    let (links, uid, gid) = (1_u64, 0_u32, 0_u32);
+    let mtime = md
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let git_state = git_state_name(info.git_state).map_or("null".to_string(), json_string);
+    let target = if file_type.is_symlink() {
+        fs::read_link(&info.path).ok()
+    } else {
+        None
+    };
+    let target = target.map_or("null".to_string(), |t| json_string(&display_path(&t, opts)));
+    
+    let mut out = format!(
+        "{{\"name\":{},\"path\":{},\"type\":{},\"size\":{},\"mtime_unix\":{},\"permissions\":{},\"nlink\":{},\"uid\":{},\"gid\":{},\"git_state\":{},\"symlink_target\":{}",
+        json_string(&display_path(Path::new(&info.name), opts)),
+        json_string(&display_path(&info.path, opts)),
+        json_string(json_type(&file_type)),
+        entry_size(info),
+        mtime,
+        json_string(&perm_string(&file_type, md)),
+        links,
+        uid,
+        gid,
+        git_state,
+        target,
+    );
+    if let Some(children) = children {
+        out.push_str(",\"children\":[");
+        out.push_str(&children.join(","));
+        out.push(']');
+    }
+    out.push('}');
+    out
+}
+
+/// `--tree --json`: the objects for the entries of `current`, each
+/// directory nested with its own entries down to `depth` levels.
+fn tree_json(current: &Path, depth: usize, opts: &Options, git_map: &GitMap, counts: &mut Counts, progress: &mut Progress) -> Vec<String> {
+    let mut entries = match read_entries(current, opts, git_map, counts, progress, None) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+    sort_entries(&mut entries, opts, opts.sort_dirs_first, opts.sort_files_first);
+    
+    let mut objects = Vec::new();
+    for info in &entries {
+        let children = if enters(info) && depth > 1 {
+            let next = if depth == usize::MAX { depth } else { depth - 1 };
+            Some(tree_json(&info.path, next, opts, git_map, counts, progress))
+        } else {
+            None
+        };
+        objects.push(entry_json(info, children.as_deref(), opts));
     }
-}
\ No newline at end of file
+    objects
+}
+
+const CSV_HEADER: &str = "name,type,size,mtime,permissions,git";
+
+/// Quotes a CSV field when it holds a comma, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// One `--csv` row; `depth` is only given in tree mode.
+fn csv_row(info: &EntryInfo, name: &str, depth: Option<usize>, opts: &Options) -> String {
+    let md = &info.metadata;
+    let file_type = md.file_type();
+    let mtime = md.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut row = [
+        csv_field(name),
+        json_type(&file_type).to_string(),
+        csv_field(&format_size(entry_size(info), opts.human_readable, opts.si)),
+        format_time(mtime, opts).trim_start().to_string(),
+        perm_string(&file_type, md),
+        git_letter(info.git_state).to_string(),
+    ]
+    .join(",");
+    if let Some(depth) = depth {
+        row.push(',');
+        row.push_str(&depth.to_string());
+    }
+    row
+}
+
+fn print_summary_json(path: &Path, counts: &Counts, opts: &Options, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"path\":{},\"dirs\":{},\"files\":{},\"symlinks\":{},\"broken_symlinks\":{},\"pipes\":{},\"sockets\":{},\"block_devices\":{},\"char_devices\":{},\"total_bytes\":{}}}",
+        json_string(&display_path(path, opts)),
+        counts.dirs,
+        counts.files,
+        counts.symlinks,
+        counts.broken_symlinks,
+        counts.pipes,
+        counts.sockets,
+        counts.block_devices,
+        counts.char_devices,
+        counts.total_bytes,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty directory under the system temp dir for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rdir-unit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An entry for `path` as read_entries would build it, without git state.
+    fn entry(path: &Path) -> EntryInfo {
+        EntryInfo {
+            path: path.to_path_buf(),
+            name: path.file_name().unwrap().to_os_string(),
+            metadata: fs::symlink_metadata(path).unwrap(),
+            icon: "",
+            git_state: GitState::None,
+            git_flags: GitFlags::CLEAN,
+            renamed_from: None,
+            hidden_links: 0,
+            link_group: None,
+            is_new: false,
+            total_size: None,
+        }
+    }
+
+    fn names(entries: &[EntryInfo]) -> Vec<String> {
+        entries.iter().map(|info| info.name.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn self_test_passes_with_the_real_width_function() {
+        assert!(self_test(visible_len));
+    }
+
+    #[test]
+    fn corrupted_width_functions_fail_the_self_test() {
+        assert!(!self_test(|s| s.len()));
+        assert!(!self_test(|s| s.chars().count()));
+        assert!(!self_test(|s| visible_len(s).max(1)));
+    }
+
+    #[test]
+    fn special_bits_show_in_the_execute_slots() {
+        assert_eq!(mode_string(0o755), "rwxr-xr-x");
+        assert_eq!(mode_string(0o4755), "rwsr-xr-x");
+        assert_eq!(mode_string(0o4644), "rwSr--r--");
+        assert_eq!(mode_string(0o2755), "rwxr-sr-x");
+        assert_eq!(mode_string(0o2745), "rwxr-Sr-x");
+        assert_eq!(mode_string(0o1777), "rwxrwxrwt");
+        assert_eq!(mode_string(0o1776), "rwxrwxrwT");
+        assert_eq!(mode_string(0o7000), "--S--S--T");
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+        assert!(glob_match("?ain.*", "main.rs"));
+        assert!(glob_match("[a-m]*", "main.rs"));
+        assert!(!glob_match("[!m]*", "main.rs"));
+        assert!(glob_match("*a*b*", "xaxxbx"));
+        assert!(glob_match("[", "["));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn tree_depth_zero_or_negative_means_everything() {
+        assert_eq!(parse_tree_depth("0"), Some(usize::MAX));
+        assert_eq!(parse_tree_depth("-2"), Some(usize::MAX));
+        assert_eq!(parse_tree_depth("1"), Some(1));
+        assert_eq!(parse_tree_depth("deep"), None);
+    }
+
+    #[test]
+    fn neighbouring_tree_levels_get_different_depth_colours() {
+        let scheme = ColorScheme::dark();
+        assert_ne!(scheme.depth_color(1), scheme.depth_color(2));
+        assert_eq!(scheme.depth_color(1), scheme.depth_color(1 + scheme.depth.len()));
+    }
+
+    /// Two directories and three files with known ages and sizes, sorted by
+    /// sort_entries under `opts`.
+    fn sorted_listing(name: &str, opts: &Options, dirs_first: bool, files_first: bool) -> Vec<String> {
+        let dir = scratch_dir(name);
+        let now = SystemTime::now();
+        let rows: [(&str, bool, u64, usize); 5] =
+            [("e", false, 5, 10), ("a", true, 1, 0), ("c", false, 9, 30), ("d", true, 7, 0), ("b", false, 2, 20)];
+        for (entry_name, is_dir, age, size) in rows {
+            let path = dir.join(entry_name);
+            if is_dir {
+                fs::create_dir(&path).unwrap();
+            } else {
+                fs::write(&path, vec![b'x'; size]).unwrap();
+            }
+            fs::File::open(&path).unwrap().set_modified(now - Duration::from_secs(1000 - age * 100)).unwrap();
+        }
+        let mut entries: Vec<EntryInfo> = rows.iter().map(|row| entry(&dir.join(row.0))).collect();
+        sort_entries(&mut entries, opts, dirs_first, files_first);
+        fs::remove_dir_all(&dir).unwrap();
+        names(&entries)
+    }
+
+    #[test]
+    fn dirs_first_keeps_each_group_in_time_order() {
+        let opts = Options { sort_key: SortKey::Time, ..Options::default() };
+        assert_eq!(sorted_listing("sd-time", &opts, true, false), ["d", "a", "c", "e", "b"]);
+    }
+
+    #[test]
+    fn files_first_keeps_each_group_in_size_order() {
+        let opts = Options { sort_key: SortKey::Size, ..Options::default() };
+        let sorted = sorted_listing("sf-size", &opts, false, true);
+        assert_eq!(sorted[..3], ["c", "b", "e"]);
+        let mut dirs = sorted[3..].to_vec();
+        dirs.sort();
+        assert_eq!(dirs, ["a", "d"]);
+    }
+
+    #[test]
+    fn dirs_first_alone_groups_then_sorts_by_name() {
+        let opts = Options::default();
+        assert_eq!(sorted_listing("sd-name", &opts, true, false), ["a", "d", "b", "c", "e"]);
+    }
+
+    #[test]
+    fn tiny_sizes_in_a_shared_unit_keep_a_significant_digit() {
+        assert_eq!(size_parts_in_unit(1, 3, false), ("0.0000000009".to_string(), SIZE_UNITS[3]));
+        assert_eq!(size_parts_in_unit(500, 2, true), ("0.0005".to_string(), SI_SIZE_UNITS[2]));
+        assert_eq!(size_parts_in_unit(100 * 1024, 2, false), ("0.1".to_string(), SIZE_UNITS[2]));
+        assert_eq!(size_parts_in_unit(1, 2, false), ("0.000001".to_string(), SIZE_UNITS[2]));
+        assert_eq!(size_parts_in_unit(2, 2, false), ("0.000002".to_string(), SIZE_UNITS[2]));
+        assert_eq!(size_parts_in_unit(3 * 1024 * 1024, 2, false), ("3.0".to_string(), SIZE_UNITS[2]));
+        assert_eq!(size_parts_in_unit(0, 2, false), ("0.0".to_string(), SIZE_UNITS[2]));
+    }
+
+    #[test]
+    fn si_sizes_count_in_thousands() {
+        assert_eq!(format_size(999, true, true), "999B");
+        assert_eq!(format_size(1000, true, true), "1.0KB");
+        assert_eq!(format_size(1000, true, false), "1000B");
+        assert_eq!(format_size(1024, true, false), "1.0K");
+        assert_eq!(format_size(1_500_000, true, true), "1.5MB");
+        assert_eq!(format_size(5_000_000_000, true, true), "5.0GB");
+        assert_eq!(format_size(5_000_000_000, false, true), "5000000000");
+    }
+
+    #[test]
+    fn pages_are_counted_in_entries() {
+        let mut pager = Pager { page_size: 2, total: 5, printed: 0 };
+        assert_eq!(pager.page_len(), 2);
+        assert_eq!(pager.advance(1), None);
+        assert_eq!(pager.advance(1).as_deref(), Some("-- page 1/3, 3 more --"));
+        assert_eq!(pager.advance(2).as_deref(), Some("-- page 2/3, 1 more --"));
+        assert_eq!(pager.advance(1), None);
+        let idle = Pager { page_size: 0, total: 5, printed: 0 };
+        assert_eq!(idle.page_len(), 5);
+    }
+
+    #[test]
+    fn total_size_walk_ticks_progress() {
+        let dir = scratch_dir("total-size-progress");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/one"), "1").unwrap();
+        fs::write(dir.join("a/b/two"), "22").unwrap();
+        let mut progress = Progress::new(true, true);
+        assert_eq!(dir_total_size(&dir, &mut progress), 3);
+        assert_eq!(progress.processed, 4);
+        progress.clear();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fresh_files_report_a_recent_birth_time() {
+        let dir = scratch_dir("btime");
+        let before = SystemTime::now() - Duration::from_secs(5);
+        fs::write(dir.join("new"), "").unwrap();
+        // tmpfs and some older filesystems keep no birth time at all.
+        if let Some(born) = btime(&dir.join("new")) {
+            assert!(born >= before && born <= SystemTime::now(), "{:?}", born);
+        }
+        assert_eq!(btime(&dir.join("missing")), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nerd_icons_avoid_the_retired_mdi_range() {
+        use symbols::Kind;
+        let kinds = [
+            Kind::Directory,
+            Kind::Symlink,
+            Kind::Pipe,
+            Kind::Socket,
+            Kind::BlockDevice,
+            Kind::CharDevice,
+            Kind::GenericFile,
+            Kind::Rust,
+            Kind::Image,
+            Kind::Archive,
+        ];
+        for kind in kinds {
+            let icon = symbols::nerd_icon(kind);
+            assert!(icon.chars().all(|c| !('\u{f500}'..='\u{fd46}').contains(&c)), "{:?} uses {:?}", kind, icon);
+        }
+        assert_eq!(symbols::nerd_icon(Kind::Rust), "\u{e7a8}");
+    }
+
+    #[test]
+    fn list_colors_shows_every_field_in_its_own_colour() {
+        for scheme in [ColorScheme::dark(), ColorScheme::light()] {
+            let mut out = Vec::new();
+            print_colors(&scheme, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let lines: Vec<&str> = out.lines().collect();
+            assert_eq!(lines.len(), scheme.fields().len());
+            for ((name, code), line) in scheme.fields().iter().zip(lines) {
+                assert!(line.starts_with(name), "{:?}", line);
+                assert!(line.contains(&format!("{}{}{}", code, name, scheme.reset)), "{:?}", line);
+                assert!(line.ends_with(&code.replace('\x1b', "\\e")), "{:?}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn watch_reflows_the_grid_when_the_width_changes() {
+        let dir = scratch_dir("watch-width");
+        for name in ["aaaa", "bbbb", "cccc", "dddd"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let opts = Options { color_scheme: ColorScheme::plain(), icon_mode: IconMode::Never, ..Options::default() };
+        let mut widths = vec![80, 14].into_iter();
+        let mut rounds = 0;
+        let mut out = Vec::new();
+        watch_paths(std::slice::from_ref(&dir), &opts, &mut out, &mut || widths.next().unwrap(), &mut || {
+            rounds += 1;
+            rounds < 2
+        })
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<usize> = out.split("\x1b[H\x1b[2J").skip(1).map(|round| round.lines().count()).collect();
+        assert_eq!(lines, [1, 2], "{:?}", out);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
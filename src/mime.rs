@@ -0,0 +1,84 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Text,
+    Image,
+    Audio,
+    Video,
+    Application,
+}
+
+pub fn guess(path: &std::path::Path) -> Option<&'static str> {
+    let ext = path.extension().and_then(|s| s.to_str())?.to_ascii_lowercase();
+    let mime = match ext.as_str() {
+        "txt" | "text" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "xml" => "text/xml",
+        "rs" => "text/x-rust",
+        "c" | "h" => "text/x-c",
+        "cpp" | "hpp" | "cc" | "cxx" => "text/x-c++",
+        "py" => "text/x-python",
+        "rb" => "text/x-ruby",
+        "go" => "text/x-go",
+        "java" => "text/x-java",
+        "sh" | "bash" | "zsh" => "text/x-shellscript",
+        "toml" => "text/x-toml",
+        "yaml" | "yml" => "text/yaml",
+        "js" => "text/javascript",
+        "ts" => "text/x-typescript",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "tif" | "tiff" => "image/tiff",
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "aac" => "audio/aac",
+        "m4a" => "audio/mp4",
+        "opus" => "audio/opus",
+        "mp4" | "m4v" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "mov" => "video/quicktime",
+        "wmv" => "video/x-ms-wmv",
+        "flv" => "video/x-flv",
+        "webm" => "video/webm",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "tar" => "application/x-tar",
+        "gz" | "tgz" => "application/gzip",
+        "bz2" => "application/x-bzip2",
+        "xz" => "application/x-xz",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "rtf" => "application/rtf",
+        "ppt" | "pptx" | "odp" => "application/vnd.ms-powerpoint",
+        "xls" | "xlsx" | "ods" => "application/vnd.ms-excel",
+        "sql" => "application/sql",
+        "db" | "sqlite" => "application/vnd.sqlite3",
+        "wasm" => "application/wasm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+pub fn category(mime: &str) -> Category {
+    match mime.split('/').next() {
+        Some("text") => Category::Text,
+        Some("image") => Category::Image,
+        Some("audio") => Category::Audio,
+        Some("video") => Category::Video,
+        _ => Category::Application,
+    }
+}
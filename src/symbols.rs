@@ -31,63 +31,382 @@ pub const SPREADSHEET: &str = "📊";
 pub const DATABASE: &str = "🗄";
 pub const LOG: &str = "📜";
 pub const LOCK: &str = "🔒";
+pub const LUA: &str = "🌙";
+pub const PHP: &str = "🐘";
+pub const SWIFT: &str = "🐦";
+pub const KOTLIN: &str = "🟪";
+pub const SCALA: &str = "🔺";
+pub const ELIXIR: &str = "💧";
+pub const HASKELL: &str = "🧮";
+pub const CLOJURE: &str = "🌀";
+pub const DART: &str = "🎯";
+pub const VUE: &str = "💚";
+pub const TEX: &str = "📐";
+pub const FONT: &str = "🔤";
+pub const EBOOK: &str = "📚";
+pub const DISK_IMAGE: &str = "💿";
+pub const PACKAGE: &str = "📥";
+pub const BUILD: &str = "🔨";
+pub const DOCKER: &str = "🐳";
+pub const GIT: &str = "🔀";
+pub const LICENSE: &str = "📃";
 
-pub fn get_file_icon(file_type: &std::fs::FileType, path: &std::path::Path) -> &'static str {
+/// What an entry is, as far as picking an icon goes.  Each icon set maps
+/// every kind to one of its glyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Directory,
+    Symlink,
+    Pipe,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    GenericFile,
+    Rust,
+    Ruby,
+    Python,
+    JavaScript,
+    Go,
+    Shell,
+    CCpp,
+    Java,
+    Markdown,
+    Text,
+    Json,
+    Config,
+    Html,
+    Css,
+    Archive,
+    Image,
+    Audio,
+    Video,
+    Pdf,
+    Document,
+    Presentation,
+    Spreadsheet,
+    Database,
+    Log,
+    Lock,
+    Lua,
+    Php,
+    Swift,
+    Kotlin,
+    Scala,
+    Elixir,
+    Haskell,
+    Clojure,
+    Dart,
+    Vue,
+    Tex,
+    Font,
+    Ebook,
+    DiskImage,
+    Package,
+    Build,
+    Docker,
+    Git,
+    License,
+}
+
+pub fn get_file_kind(file_type: &std::fs::FileType, path: &std::path::Path) -> Kind {
     if file_type.is_dir() {
-        return DIRECTORY;
+        return Kind::Directory;
     }
     if file_type.is_symlink() {
-        return SYMLINK;
+        return Kind::Symlink;
     }
 
     #[cfg(unix)]
     {
         use std::os::unix::fs::FileTypeExt;
         if file_type.is_fifo() {
-            return PIPE;
+            return Kind::Pipe;
         }
         if file_type.is_socket() {
-            return SOCKET;
+            return Kind::Socket;
         }
         if file_type.is_block_device() {
-            return BLOCK_DEVICE;
+            return Kind::BlockDevice;
         }
         if file_type.is_char_device() {
-            return CHAR_DEVICE;
+            return Kind::CharDevice;
         }
     }
 
+    if let Some(kind) = path.file_name().and_then(|s| s.to_str()).and_then(special_file_kind) {
+        return kind;
+    }
+
     if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
         let ext = ext.to_ascii_lowercase();
         match ext.as_str() {
-            "rs" => RUST,
-            "rb" => RUBY,
-            "py" => PYTHON,
-            "js" | "ts" => JAVASCRIPT,
-            "go" => GO,
-            "sh" | "zsh" | "bash" => SHELL,
-            "c" | "h" | "cpp" | "hpp" | "cc" | "cxx" => C_CPP,
-            "java" => JAVA,
-            "md" | "markdown" => MARKDOWN,
-            "txt" | "text" => TEXT,
-            "json" => JSON,
-            "toml" | "yaml" | "yml" => CONFIG,
-            "html" | "htm" => HTML,
-            "css" => CSS,
-            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "7z" | "rar" => ARCHIVE,
-            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" => IMAGE,
-            "mp3" | "flac" | "ogg" | "wav" | "aac" => AUDIO,
-            "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" => VIDEO,
-            "pdf" => PDF,
-            "doc" | "docx" | "odt" | "rtf" => DOCUMENT,
-            "ppt" | "pptx" | "odp" => PRESENTATION,
-            "xls" | "xlsx" | "ods" | "csv" => SPREADSHEET,
-            "sql" | "db" | "sqlite" => DATABASE,
-            "log" => LOG,
-            "lock" => LOCK,
-            _ => GENERIC_FILE,
+            "rs" => Kind::Rust,
+            "rb" | "rake" | "gemspec" | "erb" => Kind::Ruby,
+            "py" | "pyi" | "pyw" => Kind::Python,
+            "js" | "ts" | "jsx" | "tsx" | "mjs" | "cjs" => Kind::JavaScript,
+            "go" => Kind::Go,
+            "sh" | "zsh" | "bash" | "fish" => Kind::Shell,
+            "c" | "h" | "cpp" | "hpp" | "cc" | "cxx" => Kind::CCpp,
+            "java" => Kind::Java,
+            "lua" => Kind::Lua,
+            "php" => Kind::Php,
+            "swift" => Kind::Swift,
+            "kt" | "kts" => Kind::Kotlin,
+            "scala" | "sc" => Kind::Scala,
+            "ex" | "exs" => Kind::Elixir,
+            "hs" | "lhs" => Kind::Haskell,
+            "clj" | "cljs" | "cljc" | "edn" => Kind::Clojure,
+            "dart" => Kind::Dart,
+            "vue" => Kind::Vue,
+            "md" | "markdown" | "mdx" => Kind::Markdown,
+            "txt" | "text" => Kind::Text,
+            "tex" | "sty" | "bib" => Kind::Tex,
+            "json" => Kind::Json,
+            "toml" | "yaml" | "yml" | "ini" | "conf" | "cfg" | "env" => Kind::Config,
+            "html" | "htm" => Kind::Html,
+            "css" | "scss" | "sass" | "less" => Kind::Css,
+            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "7z" | "rar" | "zst" | "lz" | "lzma" => Kind::Archive,
+            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" | "tif" | "tiff" | "heic" => Kind::Image,
+            "mp3" | "flac" | "ogg" | "wav" | "aac" | "m4a" | "opus" => Kind::Audio,
+            "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" | "webm" | "m4v" => Kind::Video,
+            "ttf" | "otf" | "woff" | "woff2" | "eot" => Kind::Font,
+            "epub" | "mobi" | "azw" | "azw3" => Kind::Ebook,
+            "iso" | "img" | "dmg" | "vhd" | "vmdk" | "qcow2" => Kind::DiskImage,
+            "deb" | "rpm" | "apk" | "msi" | "snap" | "flatpak" | "appimage" => Kind::Package,
+            "pdf" => Kind::Pdf,
+            "doc" | "docx" | "odt" | "rtf" => Kind::Document,
+            "ppt" | "pptx" | "odp" => Kind::Presentation,
+            "xls" | "xlsx" | "ods" | "csv" => Kind::Spreadsheet,
+            "sql" | "db" | "sqlite" => Kind::Database,
+            "log" => Kind::Log,
+            "lock" => Kind::Lock,
+            _ => Kind::GenericFile,
         }
     } else {
-        GENERIC_FILE
+        Kind::GenericFile
+    }
+}
+
+/// Well-known file names that say more than their extension (or lack of
+/// one) does.  Matched case-insensitively.
+fn special_file_kind(name: &str) -> Option<Kind> {
+    let name = name.to_ascii_lowercase();
+    let kind = match name.as_str() {
+        "makefile" | "gnumakefile" | "cmakelists.txt" | "justfile" | "rakefile" => Kind::Build,
+        "dockerfile" | "containerfile" | ".dockerignore" | "docker-compose.yml" | "docker-compose.yaml"
+        | "compose.yml" | "compose.yaml" => Kind::Docker,
+        ".gitignore" | ".gitattributes" | ".gitmodules" | ".gitkeep" | ".mailmap" => Kind::Git,
+        "license" | "licence" | "license.md" | "license.txt" | "copying" | "unlicense" => Kind::License,
+        "cargo.toml" | "cargo.lock" => Kind::Rust,
+        "package.json" | "package-lock.json" | ".npmrc" => Kind::JavaScript,
+        "gemfile" | "gemfile.lock" => Kind::Ruby,
+        ".bashrc" | ".bash_profile" | ".zshrc" | ".profile" | ".inputrc" => Kind::Shell,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+pub fn emoji_icon(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Directory => DIRECTORY,
+        Kind::Symlink => SYMLINK,
+        Kind::Pipe => PIPE,
+        Kind::Socket => SOCKET,
+        Kind::BlockDevice => BLOCK_DEVICE,
+        Kind::CharDevice => CHAR_DEVICE,
+        Kind::GenericFile => GENERIC_FILE,
+        Kind::Rust => RUST,
+        Kind::Ruby => RUBY,
+        Kind::Python => PYTHON,
+        Kind::JavaScript => JAVASCRIPT,
+        Kind::Go => GO,
+        Kind::Shell => SHELL,
+        Kind::CCpp => C_CPP,
+        Kind::Java => JAVA,
+        Kind::Markdown => MARKDOWN,
+        Kind::Text => TEXT,
+        Kind::Json => JSON,
+        Kind::Config => CONFIG,
+        Kind::Html => HTML,
+        Kind::Css => CSS,
+        Kind::Archive => ARCHIVE,
+        Kind::Image => IMAGE,
+        Kind::Audio => AUDIO,
+        Kind::Video => VIDEO,
+        Kind::Pdf => PDF,
+        Kind::Document => DOCUMENT,
+        Kind::Presentation => PRESENTATION,
+        Kind::Spreadsheet => SPREADSHEET,
+        Kind::Database => DATABASE,
+        Kind::Log => LOG,
+        Kind::Lock => LOCK,
+        Kind::Lua => LUA,
+        Kind::Php => PHP,
+        Kind::Swift => SWIFT,
+        Kind::Kotlin => KOTLIN,
+        Kind::Scala => SCALA,
+        Kind::Elixir => ELIXIR,
+        Kind::Haskell => HASKELL,
+        Kind::Clojure => CLOJURE,
+        Kind::Dart => DART,
+        Kind::Vue => VUE,
+        Kind::Tex => TEX,
+        Kind::Font => FONT,
+        Kind::Ebook => EBOOK,
+        Kind::DiskImage => DISK_IMAGE,
+        Kind::Package => PACKAGE,
+        Kind::Build => BUILD,
+        Kind::Docker => DOCKER,
+        Kind::Git => GIT,
+        Kind::License => LICENSE,
+    }
+}
+
+/// Glyphs from the Nerd Fonts private use area, for terminals with a
+/// patched font.  Material Design glyphs come from the nf-md range
+/// (U+F0001 and up); Nerd Fonts 3 removed the older nf-mdi one.
+pub fn nerd_icon(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Directory => "\u{f115}",
+        Kind::Symlink => "\u{f481}",
+        Kind::Pipe => "\u{f07e5}",
+        Kind::Socket => "\u{f1e6}",
+        Kind::BlockDevice => "\u{f0a0}",
+        Kind::CharDevice => "\u{e601}",
+        Kind::GenericFile => "\u{f15b}",
+        Kind::Rust => "\u{e7a8}",
+        Kind::Ruby => "\u{e21e}",
+        Kind::Python => "\u{e606}",
+        Kind::JavaScript => "\u{e74e}",
+        Kind::Go => "\u{e627}",
+        Kind::Shell => "\u{f489}",
+        Kind::CCpp => "\u{e61d}",
+        Kind::Java => "\u{e738}",
+        Kind::Markdown => "\u{e609}",
+        Kind::Text => "\u{f15c}",
+        Kind::Json => "\u{e60b}",
+        Kind::Config => "\u{e615}",
+        Kind::Html => "\u{f13b}",
+        Kind::Css => "\u{e749}",
+        Kind::Archive => "\u{f410}",
+        Kind::Image => "\u{f1c5}",
+        Kind::Audio => "\u{f001}",
+        Kind::Video => "\u{f03d}",
+        Kind::Pdf => "\u{f1c1}",
+        Kind::Document => "\u{f1c2}",
+        Kind::Presentation => "\u{f1c4}",
+        Kind::Spreadsheet => "\u{f1c3}",
+        Kind::Database => "\u{f1c0}",
+        Kind::Log => "\u{f18d}",
+        Kind::Lock => "\u{f023}",
+        Kind::Lua => "\u{e620}",
+        Kind::Php => "\u{e608}",
+        Kind::Swift => "\u{e755}",
+        Kind::Kotlin => "\u{e634}",
+        Kind::Scala => "\u{e737}",
+        Kind::Elixir => "\u{e62d}",
+        Kind::Haskell => "\u{e61f}",
+        Kind::Clojure => "\u{e768}",
+        Kind::Dart => "\u{e798}",
+        Kind::Vue => "\u{e6a0}",
+        Kind::Tex => "\u{e69b}",
+        Kind::Font => "\u{f031}",
+        Kind::Ebook => "\u{f02d}",
+        Kind::DiskImage => "\u{f0a0}",
+        Kind::Package => "\u{f487}",
+        Kind::Build => "\u{e673}",
+        Kind::Docker => "\u{f308}",
+        Kind::Git => "\u{f1d3}",
+        Kind::License => "\u{e60a}",
+    }
+}
+
+/// Plain ASCII tokens for terminals that cannot show emoji at all.
+pub fn ascii_icon(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Directory => "[d]",
+        Kind::Symlink => "[l]",
+        Kind::Pipe => "[p]",
+        Kind::Socket => "[s]",
+        Kind::BlockDevice => "[blk]",
+        Kind::CharDevice => "[chr]",
+        Kind::GenericFile => "[f]",
+        Kind::Rust => "[rs]",
+        Kind::Ruby => "[rb]",
+        Kind::Python => "[py]",
+        Kind::JavaScript => "[js]",
+        Kind::Go => "[go]",
+        Kind::Shell => "[sh]",
+        Kind::CCpp => "[c]",
+        Kind::Java => "[java]",
+        Kind::Markdown => "[md]",
+        Kind::Text => "[txt]",
+        Kind::Json => "[json]",
+        Kind::Config => "[cfg]",
+        Kind::Html => "[html]",
+        Kind::Css => "[css]",
+        Kind::Archive => "[arc]",
+        Kind::Image => "[img]",
+        Kind::Audio => "[aud]",
+        Kind::Video => "[vid]",
+        Kind::Pdf => "[pdf]",
+        Kind::Document => "[doc]",
+        Kind::Presentation => "[ppt]",
+        Kind::Spreadsheet => "[xls]",
+        Kind::Database => "[db]",
+        Kind::Log => "[log]",
+        Kind::Lock => "[lck]",
+        Kind::Lua => "[lua]",
+        Kind::Php => "[php]",
+        Kind::Swift => "[swift]",
+        Kind::Kotlin => "[kt]",
+        Kind::Scala => "[scala]",
+        Kind::Elixir => "[ex]",
+        Kind::Haskell => "[hs]",
+        Kind::Clojure => "[clj]",
+        Kind::Dart => "[dart]",
+        Kind::Vue => "[vue]",
+        Kind::Tex => "[tex]",
+        Kind::Font => "[font]",
+        Kind::Ebook => "[book]",
+        Kind::DiskImage => "[iso]",
+        Kind::Package => "[pkg]",
+        Kind::Build => "[mk]",
+        Kind::Docker => "[dkr]",
+        Kind::Git => "[git]",
+        Kind::License => "[lic]",
+    }
+}
+
+/// Entries inspected by `dominant_kind` before it gives up looking further.
+pub const DOMINANT_SCAN_LIMIT: usize = 50;
+
+/// Picks the kind describing what a directory mostly holds, e.g. archives
+/// for a folder of tarballs.  Only the first `DOMINANT_SCAN_LIMIT` entries
+/// are looked at, and a kind with a specific icon has to cover more than
+/// half of the files seen; anything less returns `None`.
+pub fn dominant_kind(dir: &std::path::Path) -> Option<Kind> {
+    let read_dir = std::fs::read_dir(dir).ok()?;
+    let mut tally: Vec<(Kind, usize)> = Vec::new();
+    let mut files = 0;
+    for entry in read_dir.take(DOMINANT_SCAN_LIMIT).flatten() {
+        let file_type = match entry.file_type() {
+            Ok(ft) => ft,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            continue;
+        }
+        files += 1;
+        let kind = get_file_kind(&file_type, &entry.path());
+        match tally.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, n)) => *n += 1,
+            None => tally.push((kind, 1)),
+        }
+    }
+    let (kind, n) = tally.into_iter().max_by_key(|(_, n)| *n)?;
+    if emoji_icon(kind) == GENERIC_FILE || n * 2 <= files {
+        return None;
     }
-} 
\ No newline at end of file
+    Some(kind)
+}
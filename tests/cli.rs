@@ -0,0 +1,1104 @@
+//! Runs the built `rdir` binary on scratch directories and checks what it
+//! prints.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A directory under the system temp dir that is removed again when the
+/// test finishes.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rdir-cli-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `rdir` started in `dir`, with no colour whatever the environment of
+/// the test run.
+fn rdir(dir: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_rdir"));
+    cmd.current_dir(dir).env("NO_COLOR", "1").env("COLUMNS", "80").stdin(Stdio::null());
+    cmd
+}
+
+/// Stdout of a run that is expected to succeed.
+fn stdout(dir: &Path, args: &[&str]) -> String {
+    let out = rdir(dir).args(args).output().unwrap();
+    assert!(out.status.success(), "rdir {:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// The names a one-per-line listing of `dir` shows, in order.
+fn listed(dir: &Path, args: &[&str]) -> Vec<String> {
+    let args = [&["-1", "--icons=never"], args].concat();
+    stdout(dir, &args).lines().map(|line| line.trim_start().to_string()).collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn fd_lists_the_directory_under_an_fd_header() {
+    let tmp = Scratch::new("fd");
+    tmp.file("listed/inside.txt", "");
+    let script = format!("exec 3<listed; exec '{}' --icons=never -1 --with-header --fd=3", env!("CARGO_BIN_EXE_rdir"));
+    let out = Command::new("sh").arg("-c").arg(script).current_dir(tmp.path()).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "fd 3:\n  inside.txt\n");
+
+    // The listing follows the descriptor, not the name it was opened by,
+    // and relative paths after it are still read from where rdir started.
+    tmp.file("listed/sub/deep.txt", "");
+    let script = format!(
+        "exec 3<listed; mv listed moved; mkdir listed; exec '{}' --icons=never -1 -R --fd=3 moved/sub",
+        env!("CARGO_BIN_EXE_rdir")
+    );
+    let out = Command::new("sh").arg("-c").arg(script).current_dir(tmp.path()).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "fd 3:\n  inside.txt\n  sub\n\nfd 3/sub:\n  deep.txt\n\nmoved/sub:\n  deep.txt\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn fd_rejects_a_descriptor_that_is_not_a_directory() {
+    let tmp = Scratch::new("fd-file");
+    tmp.file("plain", "");
+    let script = format!("exec 3<plain; exec '{}' --fd=3", env!("CARGO_BIN_EXE_rdir"));
+    let out = Command::new("sh").arg("-c").arg(script).current_dir(tmp.path()).output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("cannot list fd 3"));
+}
+
+#[test]
+fn ascii_escapes_headers_stat_and_machine_formats() {
+    let tmp = Scratch::new("ascii");
+    tmp.file("d\u{fc}r/f\u{ef}le", "");
+    let header = stdout(tmp.path(), &["--ascii", "-1", "--with-header", "d\u{fc}r"]);
+    assert_eq!(header, "d\\xc3\\xbcr:\n  [f] f\\xc3\\xafle\n");
+    let recursive = stdout(tmp.path(), &["--ascii", "-1", "-R", "."]);
+    assert!(recursive.contains("./d\\xc3\\xbcr:\n"), "{}", recursive);
+    let stat = stdout(tmp.path(), &["--ascii", "--stat", "d\u{fc}r/f\u{ef}le"]);
+    assert!(stat.contains("Name: [f] f\\xc3\\xafle\n"), "{}", stat);
+    assert!(stat.contains("/d\\xc3\\xbcr/f\\xc3\\xafle\n"), "{}", stat);
+    let json = stdout(tmp.path(), &["--ascii", "--json", "d\u{fc}r"]);
+    assert!(json.starts_with(r#"[{"name":"f\\xc3\\xafle","path":"d\\xc3\\xbcr/f\\xc3\\xafle","#), "{}", json);
+    let csv = stdout(tmp.path(), &["--ascii", "--csv", "d\u{fc}r"]);
+    assert!(csv.lines().nth(1).unwrap().starts_with("f\\xc3\\xafle,file,"), "{}", csv);
+    for text in [header, recursive, stat, json, csv] {
+        assert!(text.is_ascii(), "{}", text);
+    }
+}
+
+/// `text` with the ANSI colour sequences taken out.
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c == 'm' {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[test]
+fn colorful_columns_colour_numbers_and_keep_them_aligned() {
+    let tmp = Scratch::new("colorful-columns");
+    tmp.file("big", &"x".repeat(200_000));
+    tmp.file("small", "x");
+    let long = stdout(tmp.path(), &["-l", "-i", "--icons=never", "--colorful-columns", "--color=always"]);
+    let rows: Vec<&str> = long.lines().skip(1).collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].contains("\x1b[33m195.3K\x1b[0m"), "{:?}", rows[0]);
+    assert!(rows[1].contains("\x1b[32m    1B\x1b[0m"), "{:?}", rows[1]);
+    for row in &rows {
+        assert!(row.starts_with("\x1b[35m"), "inode is not coloured: {:?}", row);
+        assert!(row.contains("\x1b[2m1\x1b[0m"), "link count is not dimmed: {:?}", row);
+    }
+    let plain: Vec<String> = rows.iter().map(|row| strip_ansi(row)).collect();
+    assert_eq!(plain[0].find("big"), plain[1].find("small"));
+    assert_eq!(plain[0].find("195.3K").map(|i| i + "195.3K".len()), plain[1].find("1B").map(|i| i + 2));
+
+    let uncoloured = stdout(tmp.path(), &["-l", "-i", "--colorful-columns"]);
+    assert!(!uncoloured.contains('\x1b'), "{:?}", uncoloured);
+}
+
+#[test]
+fn size_colours_step_up_with_the_unit() {
+    let tmp = Scratch::new("size-colour-si");
+    tmp.file("f", &"x".repeat(1010));
+    let args = ["-l", "--icons=never", "--colorful-columns", "--color=always"];
+    assert!(stdout(tmp.path(), &args).contains("\x1b[32m1010B\x1b[0m"));
+    let si = stdout(tmp.path(), &[&args[..], &["--si"]].concat());
+    assert!(si.contains("\x1b[33m1.0KB\x1b[0m"), "{:?}", si);
+}
+
+/// git in `dir` with a fixed identity and no user configuration.
+fn git_command(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.current_dir(dir)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "Ada")
+        .env("GIT_AUTHOR_EMAIL", "ada@example.com")
+        .env("GIT_COMMITTER_NAME", "Ada")
+        .env("GIT_COMMITTER_EMAIL", "ada@example.com")
+        .stdout(Stdio::null());
+    cmd
+}
+
+/// Runs git in `dir` with a fixed identity and no user configuration.
+fn git(dir: &Path, args: &[&str]) {
+    let status = git_command(dir).args(args).status().unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// A repository in `tmp` with `files` committed.
+fn git_repo(tmp: &Scratch, files: &[&str]) {
+    git(tmp.path(), &["init", "-q"]);
+    for name in files {
+        tmp.file(name, "committed\n");
+    }
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "-q", "-m", "initial"]);
+}
+
+/// A directory holding a `git` that appends its arguments to `log` and
+/// then runs the real git, for putting first on `PATH`.
+#[cfg(unix)]
+fn logging_git(tmp: &Scratch, log: &Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let real = Command::new("sh").arg("-c").arg("command -v git").output().unwrap();
+    let real = String::from_utf8(real.stdout).unwrap();
+    let bin = tmp.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let script = bin.join("git");
+    fs::write(&script, format!("#!/bin/sh\necho \"$@\" >> '{}'\nexec '{}' \"$@\"\n", log.display(), real.trim())).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default())
+}
+
+#[cfg(unix)]
+#[test]
+fn stat_reports_git_state_owner_and_group() {
+    use std::os::unix::fs::MetadataExt;
+    let tmp = Scratch::new("stat");
+    let repo = Scratch::new("stat-repo");
+    git_repo(&repo, &["a.txt"]);
+    repo.file("a.txt", "changed\n");
+
+    let stat = stdout(repo.path(), &["--gs", "--stat", "a.txt"]);
+    assert!(stat.contains("    Git: M\n"), "{}", stat);
+
+    let log = tmp.path().join("git.log");
+    let path = logging_git(&tmp, &log);
+    let out = rdir(repo.path()).env("PATH", path).args(["--stat", "a.txt"]).output().unwrap();
+    let stat = String::from_utf8(out.stdout).unwrap();
+    assert!(!stat.contains("Git:"), "{}", stat);
+    assert!(!log.exists(), "git was run without --gs");
+
+    let md = fs::metadata(repo.path().join("a.txt")).unwrap();
+    let owner = stat.lines().find(|line| line.trim_start().starts_with("Owner:")).unwrap();
+    assert!(owner.ends_with(&format!(" ({})", md.uid())), "{}", owner);
+    assert!(!owner.trim_start()["Owner: ".len()..].starts_with('('), "{}", owner);
+    let group = stat.lines().find(|line| line.trim_start().starts_with("Group:")).unwrap();
+    assert!(group.ends_with(&format!(" ({})", md.gid())), "{}", group);
+}
+
+#[test]
+fn git_status_covers_names_with_tabs_and_newlines() {
+    let repo = Scratch::new("git-odd-names");
+    git_repo(&repo, &["tab\there", "new\nline", "plain"]);
+    repo.file("tab\there", "changed\n");
+    repo.file("new\nline", "changed\n");
+    git(repo.path(), &["mv", "plain", "moved"]);
+    let listing = stdout(repo.path(), &["--gs", "-1", "--icons=never"]);
+    assert_eq!(listing, "R moved (was plain)\nM new\nline\nM tab\there\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn git_cache_spares_the_second_run_a_git_status() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = Scratch::new("git-cache");
+    let repo = Scratch::new("git-cache-repo");
+    git_repo(&repo, &["plain", "tab\there", "new\nline"]);
+    for name in ["plain", "tab\there", "new\nline"] {
+        repo.file(name, "changed\n");
+    }
+    let log = tmp.path().join("git.log");
+    let path = logging_git(&tmp, &log);
+    let cache = tmp.path().join("cache");
+    let run = || {
+        let out = rdir(repo.path())
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", &cache)
+            .args(["--gs", "--git-shell-out", "--git-cache", "-1", "--icons=never"])
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let calls = || fs::read_to_string(&log).unwrap_or_default().lines().filter(|l| l.starts_with("status")).count();
+
+    let first = run();
+    assert_eq!(first, "M new\nline\nM plain\nM tab\there\n");
+    let after_first = calls();
+    assert!(after_first > 0);
+    let second = run();
+    assert_eq!(second, first);
+    assert_eq!(calls(), after_first, "the second run ran git status again");
+
+    let dir = cache.join("rdir");
+    assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+    for file in fs::read_dir(&dir).unwrap() {
+        let file = file.unwrap().path();
+        assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+        // A cache file others could have written is not trusted.
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o622)).unwrap();
+    }
+    run();
+    assert!(calls() > after_first, "a group-writable cache file was trusted");
+}
+
+#[cfg(unix)]
+#[test]
+fn git_cache_does_not_write_through_a_planted_symlink() {
+    let tmp = Scratch::new("git-cache-link");
+    let repo = Scratch::new("git-cache-link-repo");
+    git_repo(&repo, &["a"]);
+    let cache = tmp.path().join("cache");
+    let run = || {
+        let out = rdir(repo.path()).env("XDG_CACHE_HOME", &cache).args(["--gs", "--git-shell-out", "--git-cache"]).output().unwrap();
+        assert!(out.status.success());
+    };
+    run();
+    let victim = tmp.file("victim", "precious\n");
+    for file in fs::read_dir(cache.join("rdir")).unwrap() {
+        let file = file.unwrap().path();
+        fs::remove_file(&file).unwrap();
+        std::os::unix::fs::symlink(&victim, &file).unwrap();
+    }
+    run();
+    assert_eq!(fs::read_to_string(&victim).unwrap(), "precious\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn git_ignore_hides_ignored_names_with_one_git_status() {
+    let tmp = Scratch::new("git-ignore");
+    let repo = Scratch::new("git-ignore-repo");
+    git_repo(&repo, &["kept", "a/kept", "a/b/kept"]);
+    repo.file(".gitignore", "ign*\n");
+    for name in ["ign ored", "a/ign\tored", "a/b/ignored", "a/b/c/kept", "a/b/c/ignored"] {
+        repo.file(name, "");
+    }
+    let log = tmp.path().join("git.log");
+    let path = logging_git(&tmp, &log);
+    let out = rdir(repo.path())
+        .env("PATH", &path)
+        .args(["--git-ignore", "--git-shell-out", "--tree=0", "--icons=never", "--ascii", "-a", "--ignore=.git"])
+        .output()
+        .unwrap();
+    let tree = String::from_utf8(out.stdout).unwrap();
+    assert!(!tree.contains("ored"), "{}", tree);
+    assert_eq!(tree.matches("kept").count(), 4, "{}", tree);
+    let statuses = fs::read_to_string(&log).unwrap().lines().filter(|l| l.starts_with("status")).count();
+    assert_eq!(statuses, 1);
+
+    fs::remove_file(&log).unwrap();
+    let direct = rdir(repo.path())
+        .env("PATH", &path)
+        .args(["--git-ignore", "--no-git-shell-out", "--tree=0", "--icons=never", "--ascii", "-a", "--ignore=.git"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(direct.stdout).unwrap(), tree);
+    assert!(!log.exists(), "--no-git-shell-out ran git");
+}
+
+#[cfg(unix)]
+#[test]
+fn staged_changes_show_whether_or_not_git_is_run() {
+    let tmp = Scratch::new("git-staged");
+    let repo = Scratch::new("git-staged-repo");
+    git_repo(&repo, &["a", "b"]);
+    repo.file("a", "changed\n");
+    repo.file("new", "");
+    git(repo.path(), &["add", "a", "new"]);
+    let args = ["--gs", "-1", "--icons=never"];
+    let shelled = stdout(repo.path(), &[&args[..], &["--git-shell-out"]].concat());
+    assert_eq!(shelled, "M a\n  b\nA new\n");
+    assert_eq!(stdout(repo.path(), &[&args[..], &["--no-git-shell-out"]].concat()), shelled);
+    assert_eq!(stdout(repo.path(), &args), shelled);
+
+    // Once nothing is staged, the index is read without running git.
+    git(repo.path(), &["commit", "-q", "-m", "second"]);
+    repo.file("b", "changed\n");
+    let log = tmp.path().join("git.log");
+    let path = logging_git(&tmp, &log);
+    let out = rdir(repo.path()).env("PATH", path).args(args).output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "  a\nM b\n  new\n");
+    assert!(!log.exists(), "git was run for an index matching HEAD");
+}
+
+#[test]
+fn trees_show_git_markers_at_every_depth() {
+    let repo = Scratch::new("git-tree");
+    git_repo(&repo, &["top", "sub/a.txt", "sub/deeper/b.txt"]);
+    repo.file("sub/deeper/b.txt", "changed\n");
+    let expected = "|--   sub\n|   |--   a.txt\n|   `--   deeper\n|       `-- M b.txt\n`--   top\n";
+    for mode in ["--git-shell-out", "--no-git-shell-out"] {
+        assert_eq!(stdout(repo.path(), &["--tree", "--gs", "--ascii", "--icons=never", mode]), expected, "{}", mode);
+    }
+}
+
+#[test]
+fn named_files_take_blame_and_git_state_from_their_own_directory() {
+    let repo = Scratch::new("named-files");
+    git_repo(&repo, &["top", "sub/a.txt"]);
+    repo.file("sub/a.txt", "changed\n");
+    let listing = stdout(repo.path(), &["--gs", "-l", "--blame", "--icons=never", "sub/a.txt", "top"]);
+    let line = listing.lines().find(|line| line.ends_with("sub/a.txt")).unwrap();
+    assert!(line.contains(" Ada M M sub/a.txt"), "{}", listing);
+    let line = listing.lines().find(|line| line.ends_with(" top")).unwrap();
+    assert!(line.contains(" Ada "), "{}", listing);
+}
+
+#[test]
+fn summary_json_counts_the_bytes_of_named_files() {
+    let tmp = Scratch::new("summary-json-files");
+    tmp.file("five", "12345");
+    tmp.file("dir/three", "123");
+    let summary = stdout(tmp.path(), &["--summary-json", "five", "dir"]);
+    let mut lines = summary.lines();
+    let file = lines.next().unwrap();
+    assert!(file.starts_with("{\"path\":\"five\",") && file.contains("\"files\":1,") && file.ends_with("\"total_bytes\":5}"), "{}", file);
+    assert!(lines.next().unwrap().ends_with("\"total_bytes\":3}"), "{}", summary);
+}
+
+#[test]
+fn dotfiles_go_first_last_or_among_the_rest() {
+    let tmp = Scratch::new("dotfiles");
+    for name in [".b", "a", "c", ".a", "B", "#x", "-y"] {
+        tmp.file(name, "");
+    }
+    assert_eq!(listed(tmp.path(), &["-a"]), [".a", ".b", "#x", "-y", "a", "B", "c"]);
+    assert_eq!(listed(tmp.path(), &["-a", "--dotfiles=first"]), [".a", ".b", "#x", "-y", "a", "B", "c"]);
+    assert_eq!(listed(tmp.path(), &["-a", "--dotfiles=last"]), ["#x", "-y", "a", "B", "c", ".a", ".b"]);
+    assert_eq!(listed(tmp.path(), &["-a", "--dotfiles=mixed"]), ["#x", "-y", ".a", "a", ".b", "B", "c"]);
+
+    // The grouping outranks the sort key: an old dotfile still leads with -t.
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::File::options().write(true).open(tmp.path().join(".a")).unwrap().set_modified(old).unwrap();
+    let by_time = listed(tmp.path(), &["-a", "-t", "--dotfiles=first"]);
+    assert_eq!(by_time[..2], [".b", ".a"], "{:?}", by_time);
+    let by_time = listed(tmp.path(), &["-a", "-t", "--dotfiles=last"]);
+    assert_eq!(by_time[5..], [".b", ".a"], "{:?}", by_time);
+}
+
+#[cfg(unix)]
+#[test]
+fn perm_matches_any_all_or_exact_bits_like_find() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = Scratch::new("perm");
+    for (name, mode) in [("open", 0o602), ("tool", 0o755), ("plain", 0o644), ("half", 0o710)] {
+        let path = tmp.file(name, "");
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+    assert_eq!(listed(tmp.path(), &["--perm=/002"]), ["open"]);
+    assert_eq!(listed(tmp.path(), &["--perm=/011"]), ["half", "tool"]);
+    assert_eq!(listed(tmp.path(), &["--perm=-111"]), ["tool"]);
+    assert_eq!(listed(tmp.path(), &["--perm=644"]), ["plain"]);
+}
+
+#[test]
+fn names_equal_but_for_case_keep_one_order() {
+    let tmp = Scratch::new("name-order");
+    tmp.file("file", "");
+    tmp.file("File", "");
+    tmp.file("b", "");
+    fs::hard_link(tmp.path().join("file"), tmp.path().join("a")).unwrap();
+    let first = listed(tmp.path(), &[]);
+    assert_eq!(first, ["a", "b", "File", "file"]);
+    for _ in 0..5 {
+        assert_eq!(listed(tmp.path(), &[]), first);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn resolve_links_shows_relative_targets_as_absolute_paths() {
+    let tmp = Scratch::new("resolve-links");
+    fs::create_dir_all(tmp.path().join("d/sub")).unwrap();
+    std::os::unix::fs::symlink("../shared/lib.so", tmp.path().join("d/sub/link")).unwrap();
+    assert_eq!(listed(tmp.path(), &["d/sub"]), ["link -> ../shared/lib.so"]);
+    let target = tmp.path().join("d/shared/lib.so");
+    assert_eq!(listed(tmp.path(), &["--resolve-links", "d/sub"]), [format!("link -> {}", target.display())]);
+}
+
+#[test]
+fn files0_from_lists_nul_separated_paths() {
+    use std::io::Write;
+    let tmp = Scratch::new("files0-from");
+    tmp.file("a b", "");
+    tmp.file("sub/c d", "");
+    tmp.file("skipped", "");
+    let list = tmp.file("list", "./a b\0./sub/c d\0");
+    assert_eq!(listed(tmp.path(), &[&format!("--files0-from={}", list.display())]), ["./a b", "./sub/c d"]);
+
+    let mut child = rdir(tmp.path())
+        .args(["-1", "--icons=never", "--files0-from=-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"./sub/c d\0./a b\0").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "  ./a b\n  ./sub/c d\n");
+}
+
+#[test]
+fn compact_drops_the_blank_line_between_paths() {
+    let tmp = Scratch::new("compact");
+    tmp.file("one/a", "");
+    tmp.file("two/b", "");
+    let spaced = stdout(tmp.path(), &["-1", "--icons=never", "one", "two"]);
+    assert_eq!(spaced, "one:\n  a\n\ntwo:\n  b\n");
+    let compact = stdout(tmp.path(), &["-1", "--icons=never", "--compact", "one", "two"]);
+    assert_eq!(compact, "one:\n  a\ntwo:\n  b\n");
+}
+
+#[test]
+fn reverse_mirrors_each_tree_level_and_its_connectors() {
+    let tmp = Scratch::new("reverse-tree");
+    tmp.file("a/x/keep", "");
+    tmp.file("a/y", "");
+    tmp.file("b/keep", "");
+    tmp.file("c", "");
+    let tree = stdout(tmp.path(), &["--tree=2", "--ascii", "--icons=never", "-r"]);
+    assert_eq!(tree, "|--   c\n|--   b\n|   `--   keep\n`--   a\n    |--   y\n    `--   x\n");
+}
+
+#[test]
+fn group_reverse_flips_within_groups_but_not_their_order() {
+    let tmp = Scratch::new("group-reverse");
+    for name in ["d1/keep", "d2/keep", "f1", "f2"] {
+        tmp.file(name, "");
+    }
+    assert_eq!(listed(tmp.path(), &["--sd"]), ["d1", "d2", "f1", "f2"]);
+    assert_eq!(listed(tmp.path(), &["--sd", "--group-reverse"]), ["d2", "d1", "f2", "f1"]);
+    assert_eq!(listed(tmp.path(), &["--sf", "--group-reverse"]), ["f2", "f1", "d2", "d1"]);
+
+    // The --dotfiles placement is a grouping too.
+    tmp.file(".f0", "");
+    assert_eq!(listed(tmp.path(), &["-a", "--group-reverse", "--dotfiles=first"]), [".f0", "f2", "f1", "d2", "d1"]);
+    assert_eq!(listed(tmp.path(), &["-a", "--group-reverse", "--dotfiles=last"]), ["f2", "f1", "d2", "d1", ".f0"]);
+}
+
+#[test]
+fn classify_mime_gives_audio_files_one_colour() {
+    let tmp = Scratch::new("classify-mime");
+    for name in ["a.flac", "b.mp3", "c.png"] {
+        tmp.file(name, "");
+    }
+    let listing = stdout(tmp.path(), &["-1", "--icons=never", "--color=always", "--classify=mime"]);
+    let colours: Vec<&str> = listing.lines().map(|line| line.trim_start().split('m').next().unwrap()).collect();
+    assert!(colours.len() == 3 && colours.iter().all(|c| c.starts_with('\x1b')), "{}", listing);
+    assert_eq!(colours[0], colours[1], "{}", listing);
+    assert_ne!(colours[0], colours[2], "{}", listing);
+}
+
+#[test]
+fn entry_terminator_ends_every_entry_including_the_last() {
+    let tmp = Scratch::new("entry-terminator");
+    tmp.file("a", "");
+    tmp.file("b", "");
+    assert_eq!(listed(tmp.path(), &["--entry-terminator=;\\t"]), ["a;\t", "b;\t"]);
+}
+
+#[test]
+fn staged_renames_name_their_source() {
+    let repo = Scratch::new("git-rename");
+    git_repo(&repo, &["old.rs"]);
+    git(repo.path(), &["mv", "old.rs", "new.rs"]);
+    assert_eq!(listed(repo.path(), &["--gs"]), ["R new.rs (was old.rs)"]);
+    let long = stdout(repo.path(), &["--gs", "-l", "--icons=never"]);
+    assert!(long.lines().any(|line| line.ends_with(" new.rs (was old.rs)")), "{}", long);
+}
+
+#[test]
+fn smart_dir_icons_give_a_folder_of_images_the_image_icon() {
+    let tmp = Scratch::new("smart-dir-icons");
+    for name in ["pics/a.jpg", "pics/b.jpg", "pics/c.jpg", "plain/x"] {
+        tmp.file(name, "");
+    }
+    let args = ["-1", "--icons=always", "--icon-set=ascii"];
+    assert_eq!(stdout(tmp.path(), &args), "  [d] pics\n  [d] plain\n");
+    let smart = stdout(tmp.path(), &[&args[..], &["--smart-dir-icons"]].concat());
+    assert_eq!(smart, "  [img] pics\n  [d] plain\n");
+}
+
+// Some filesystems, APFS among them, refuse names that are not UTF-8.
+#[cfg(target_os = "linux")]
+#[test]
+fn strict_utf8_reports_and_skips_names_that_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let tmp = Scratch::new("strict-utf8");
+    tmp.file("ok", "");
+    fs::write(tmp.path().join(OsStr::from_bytes(b"bad\xff")), "").unwrap();
+    let lossy = stdout(tmp.path(), &["-1", "--icons=never"]);
+    assert_eq!(lossy, "  bad\u{fffd}\n  ok\n");
+    let out = rdir(tmp.path()).args(["-1", "--icons=never", "--strict-utf8"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "  ok\n");
+    assert!(String::from_utf8(out.stderr).unwrap().contains("bad\\xff"));
+
+    // Named on the command line, on stdin or in a --files0-from list.
+    let bad = OsStr::from_bytes(b"bad\xff");
+    let out = rdir(tmp.path()).arg("--strict-utf8").arg("-1").arg(bad).arg("ok").output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "  \u{1f4c4} ok\n");
+    assert!(String::from_utf8(out.stderr).unwrap().contains("bad\\xff"));
+    let lossy = rdir(tmp.path()).arg("--icons=never").arg(bad).output().unwrap();
+    assert!(lossy.status.success());
+    assert_eq!(String::from_utf8(lossy.stdout).unwrap(), "  bad\u{fffd}\n");
+    let lists: [(&str, &[u8]); 2] = [("--stdin-names", b"bad\xff\nok\n"), ("--files0-from=-", b"bad\xff\0ok\0")];
+    for (flag, input) in lists {
+        let mut child = rdir(tmp.path())
+            .args([flag, "--strict-utf8", "--icons=never"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), input).unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert_eq!(out.status.code(), Some(1), "{}", flag);
+        assert_eq!(String::from_utf8(out.stdout).unwrap().trim_start(), "ok\n", "{}", flag);
+        assert!(String::from_utf8(out.stderr).unwrap().contains("bad\\xff"), "{}", flag);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn broken_links_are_highlighted_plain_or_hidden() {
+    let tmp = Scratch::new("broken-links");
+    tmp.file("sub/f", "");
+    std::os::unix::fs::symlink("nowhere", tmp.path().join("sub/dead")).unwrap();
+    let run = |mode: &str| {
+        let args = ["-1", "--icons=never", "--color=always", "--report", mode, "sub"];
+        strip_ansi(&stdout(tmp.path(), &args)).lines().last().unwrap().to_string()
+    };
+    let highlight = stdout(tmp.path(), &["-1", "--icons=never", "--color=always", "sub"]);
+    let plain = stdout(tmp.path(), &["-1", "--icons=never", "--color=always", "--broken-links=plain", "sub"]);
+    assert_ne!(highlight, plain);
+    assert_eq!(strip_ansi(&highlight), strip_ansi(&plain));
+    assert_eq!(run("--broken-links=highlight"), "1 file, 1 broken symlink, 0B total");
+    assert_eq!(run("--broken-links=plain"), "1 file, 1 symlink, 0B total");
+    assert_eq!(run("--broken-links=hide"), "1 file, 0B total");
+
+    let tree = stdout(tmp.path(), &["--tree=0", "--ascii", "--icons=never", "--broken-links=hide"]);
+    assert_eq!(tree, "`--   sub\n    `--   f\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn long0_records_split_cleanly_on_nul() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let tmp = Scratch::new("long0");
+    let path = tmp.file("a b", "hello");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    tmp.file("c", "");
+    let md = fs::metadata(&path).unwrap();
+    let out = stdout(tmp.path(), &["--long0"]);
+    let records: Vec<Vec<&str>> = out.lines().map(|record| record.split('\0').collect()).collect();
+    assert_eq!(records.len(), 2, "{:?}", out);
+    let (uid, gid, mtime) = (md.uid().to_string(), md.gid().to_string(), md.mtime().to_string());
+    assert_eq!(records[0], ["-rw-r-----", "1", &uid, &gid, "5", &mtime, "", "a b"]);
+    assert_eq!(records[1].last(), Some(&"c"));
+}
+
+#[test]
+fn highlight_marks_matching_names_only() {
+    let tmp = Scratch::new("highlight");
+    for name in ["main.rs", "notes.txt", "other"] {
+        tmp.file(name, "");
+    }
+    let listing = stdout(tmp.path(), &["-1", "--icons=never", "--color=always", "--highlight=.rs", "--highlight=note"]);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert!(lines[0].contains("\x1b[1;7m.rs\x1b[0m"), "{:?}", lines[0]);
+    assert!(lines[1].contains("\x1b[1;7mnote\x1b[0m"), "{:?}", lines[1]);
+    assert!(!lines[2].contains("\x1b[1;7m"), "{:?}", lines[2]);
+    assert_eq!(listed(tmp.path(), &["--highlight=.rs"]), ["main.rs", "notes.txt", "other"]);
+}
+
+#[test]
+fn with_header_names_a_single_path() {
+    let tmp = Scratch::new("with-header");
+    tmp.file("sub/a", "");
+    assert_eq!(stdout(tmp.path(), &["-1", "--icons=never", "sub"]), "  a\n");
+    assert_eq!(stdout(tmp.path(), &["-1", "--icons=never", "--with-header", "sub"]), "sub:\n  a\n");
+}
+
+#[test]
+fn git_position_puts_the_marker_before_the_icon_or_after_the_name() {
+    let repo = Scratch::new("git-position");
+    git_repo(&repo, &["a", "b"]);
+    repo.file("a", "changed\n");
+    let args = ["-1", "--gs", "--icon-set=ascii"];
+    assert_eq!(stdout(repo.path(), &args), "M [f] a\n  [f] b\n");
+    assert_eq!(stdout(repo.path(), &[&args[..], &["--git-position=leading"]].concat()), "M [f] a\n  [f] b\n");
+    assert_eq!(stdout(repo.path(), &[&args[..], &["--git-position=trailing"]].concat()), "[f] a M\n[f] b\n");
+}
+
+#[test]
+fn name_color_picks_type_or_git_colours() {
+    let repo = Scratch::new("name-color");
+    git_repo(&repo, &["a.txt"]);
+    repo.file("a.txt", "changed\n");
+    repo.file("new.txt", "");
+    let names = |mode: &str| stdout(repo.path(), &["--gs", "-1", "--icons=never", "--color=always", mode]);
+    // Modified is blue, untracked grey and a text file white.
+    assert_eq!(names("--name-color=type"), "\x1b[34mM\x1b[0m \x1b[37ma.txt\x1b[0m\n\x1b[90m?\x1b[0m \x1b[37mnew.txt\x1b[0m\n");
+    assert_eq!(names("--name-color=git"), "\x1b[34mM\x1b[0m \x1b[34ma.txt\x1b[0m\n\x1b[90m?\x1b[0m \x1b[90mnew.txt\x1b[0m\n");
+    assert_eq!(
+        names("--name-color=git-over-modified"),
+        "\x1b[34mM\x1b[0m \x1b[34ma.txt\x1b[0m\n\x1b[90m?\x1b[0m \x1b[37mnew.txt\x1b[0m\n"
+    );
+}
+
+#[test]
+fn ascii_icon_set_uses_bracketed_tokens() {
+    let tmp = Scratch::new("ascii-icons");
+    tmp.file("main.rs", "");
+    tmp.file("sub/keep", "");
+    let listing = stdout(tmp.path(), &["-1", "--icons=always", "--icon-set=ascii"]);
+    assert_eq!(listing, "  [rs] main.rs\n  [d] sub\n");
+    assert!(listing.is_ascii());
+}
+
+#[test]
+fn blame_shows_the_last_author_or_a_dash() {
+    let repo = Scratch::new("blame");
+    git_repo(&repo, &["tracked"]);
+    repo.file("untracked", "");
+    let long = stdout(repo.path(), &["-l", "--blame", "--icons=never"]);
+    let line = |name: &str| long.lines().find(|line| line.ends_with(name)).unwrap().to_string();
+    assert!(line(" tracked").contains(" Ada "), "{}", long);
+    assert!(line(" untracked").contains(" -   "), "{}", long);
+}
+
+#[cfg(unix)]
+#[test]
+fn dedup_shows_one_name_per_inode() {
+    let tmp = Scratch::new("dedup");
+    let first = tmp.file("a", "x");
+    fs::hard_link(&first, tmp.path().join("b")).unwrap();
+    fs::hard_link(&first, tmp.path().join("c")).unwrap();
+    tmp.file("z", "");
+    assert_eq!(listed(tmp.path(), &[]), ["a", "b", "c", "z"]);
+    let dedup = listed(tmp.path(), &["--dedup"]);
+    assert_eq!(dedup.len(), 2, "{:?}", dedup);
+    // Which of the names is seen first is up to the directory order.
+    assert!(["a", "b", "c"].iter().any(|name| dedup[0] == format!("{} (+2 links)", name)), "{:?}", dedup);
+    assert_eq!(dedup[1], "z");
+}
+
+#[cfg(unix)]
+#[test]
+fn unsorted_listings_keep_the_inode_column() {
+    let tmp = Scratch::new("unsorted-inode");
+    let file = tmp.file("a", "");
+    let ino = std::os::unix::fs::MetadataExt::ino(&fs::metadata(file).unwrap());
+    let sorted = listed(tmp.path(), &["-i"]);
+    assert!(sorted[0].starts_with(&format!("{} ", ino)), "{:?}", sorted);
+    for args in [&["-U", "-i"][..], &["--stream", "-i"]] {
+        assert_eq!(listed(tmp.path(), args), sorted);
+    }
+}
+
+#[test]
+fn sort_by_length_puts_short_names_first() {
+    let tmp = Scratch::new("sort-length");
+    for name in ["ccc", "a", "bb", "b", "aaa", "日本"] {
+        tmp.file(name, "");
+    }
+    // Wide characters count for two columns each.
+    assert_eq!(listed(tmp.path(), &["--sort=length"]), ["a", "b", "bb", "aaa", "ccc", "日本"]);
+    assert_eq!(listed(tmp.path(), &["--sort=length", "-r"]), ["日本", "ccc", "aaa", "bb", "b", "a"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn broken_link_targets_get_their_own_colour() {
+    let tmp = Scratch::new("broken-target");
+    tmp.file("ok", "");
+    std::os::unix::fs::symlink("nowhere", tmp.path().join("dead")).unwrap();
+    std::os::unix::fs::symlink("ok", tmp.path().join("live")).unwrap();
+    let listing = stdout(tmp.path(), &["-1", "--icons=never", "--color=always"]);
+    let dead = listing.lines().find(|line| line.contains("dead")).unwrap();
+    assert!(dead.starts_with("  \x1b[31mdead\x1b[0m -> \x1b[2;9;31mnowhere"), "{:?}", dead);
+    let live = listing.lines().find(|line| line.contains("live")).unwrap();
+    assert!(!live.contains("\x1b[2;9;31m"), "{:?}", live);
+}
+
+#[test]
+fn new_within_badges_files_added_to_git_recently() {
+    let repo = Scratch::new("new-within");
+    git(repo.path(), &["init", "-q"]);
+    repo.file("old", "");
+    repo.file("new", "");
+    repo.file("untracked", "");
+    git(repo.path(), &["add", "old"]);
+    let status = git_command(repo.path())
+        .env("GIT_COMMITTER_DATE", "2020-01-01T00:00:00Z")
+        .args(["commit", "-q", "-m", "old"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    git(repo.path(), &["add", "new"]);
+    git(repo.path(), &["commit", "-q", "-m", "new"]);
+    assert_eq!(listed(repo.path(), &["--new-within=7d"]), ["new [new]", "old", "untracked"]);
+    assert_eq!(listed(repo.path(), &[]), ["new", "old", "untracked"]);
+}
+
+#[test]
+fn json_lists_entries_as_objects_and_nests_trees() {
+    let tmp = Scratch::new("json");
+    tmp.file("a", "abc");
+    tmp.file("sub/b", "");
+    tmp.file(".hidden", "");
+    let json = stdout(tmp.path(), &["--json", "--color=always"]);
+    assert!(json.starts_with("[{\"name\":\"a\",\"path\":\"./a\",\"type\":\"file\",\"size\":3,\"mtime_unix\":"), "{}", json);
+    assert!(json.contains(",\"permissions\":\"-rw"), "{}", json);
+    assert!(json.contains(",\"git_state\":null,\"symlink_target\":null}"), "{}", json);
+    assert!(json.contains("{\"name\":\"sub\",\"path\":\"./sub\",\"type\":\"dir\""), "{}", json);
+    assert!(!json.contains("hidden") && !json.contains('\x1b'), "{}", json);
+    assert!(stdout(tmp.path(), &["--json", "-a"]).contains("\"name\":\".hidden\""));
+    assert!(!stdout(tmp.path(), &["--json", "-f"]).contains("\"sub\""));
+
+    let tree = stdout(tmp.path(), &["--json", "--tree=0"]);
+    assert!(tree.contains(",\"children\":[{\"name\":\"b\",\"path\":\"./sub/b\","), "{}", tree);
+    assert!(tree.trim_end().ends_with("}]}]"), "{}", tree);
+}
+
+#[test]
+fn csv_quotes_names_and_adds_depth_in_trees() {
+    let tmp = Scratch::new("csv");
+    tmp.file("q,\"x", "x");
+    tmp.file("sub/b", "");
+    tmp.file("big", &"x".repeat(2048));
+    let csv = stdout(tmp.path(), &["--csv", "--color=always"]);
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows[0], "name,type,size,mtime,permissions,git");
+    assert!(rows[1].starts_with("big,file,2.0K,"), "{}", csv);
+    assert!(rows[2].starts_with("\"q,\"\"x\",file,1B,"), "{}", csv);
+    assert!(rows[3].starts_with("sub,dir,"), "{}", csv);
+    assert!(!csv.contains('\x1b'), "{}", csv);
+    assert!(stdout(tmp.path(), &["--csv", "--non-human-readable"]).contains("\nbig,file,2048,"));
+    assert!(!stdout(tmp.path(), &["--csv", "-f"]).contains("\nsub,"));
+
+    let tree = stdout(tmp.path(), &["--csv", "--tree=0"]);
+    assert!(tree.starts_with("name,type,size,mtime,permissions,git,depth\n"), "{}", tree);
+    assert!(tree.lines().any(|row| row.starts_with("sub/b,file,0B,") && row.ends_with(",2")), "{}", tree);
+}
+
+#[test]
+fn flatten_lists_nested_entries_by_relative_path() {
+    let tmp = Scratch::new("flatten");
+    for name in ["a/b/deep", "a/one", "top"] {
+        tmp.file(name, "");
+    }
+    let flat = stdout(tmp.path(), &["--flatten", "--icons=never"]);
+    assert_eq!(flat, "a\na/b\na/b/deep\na/one\ntop\n");
+    assert_eq!(stdout(tmp.path(), &["--flatten=2", "--icons=never", "-r"]), "top\na\na/one\na/b\n");
+}
+
+#[test]
+fn sort_by_git_state_puts_changes_first() {
+    let repo = Scratch::new("sort-git");
+    git_repo(&repo, &["a", "c", "z"]);
+    repo.file("z", "changed\n");
+    repo.file("m", "");
+    git(repo.path(), &["add", "m"]);
+    repo.file("b", "");
+    repo.file("y", "");
+    assert_eq!(listed(repo.path(), &["--sort=git"]), ["M z", "A m", "? b", "? y", "a", "c"]);
+}
+
+#[test]
+fn tree_summary_icons_tally_dirs_and_files() {
+    let tmp = Scratch::new("tree-summary-icons");
+    tmp.file("a", "ab");
+    tmp.file("sub/b", "");
+    tmp.file("sub/c", "");
+    let tree = stdout(tmp.path(), &["--tree=0", "--tree-summary-icons", "--icons=always"]);
+    assert_eq!(tree.lines().last(), Some("\u{1f4c1} 1  \u{1f4c4} 3"), "{}", tree);
+    let plain = stdout(tmp.path(), &["--tree=0", "--tree-summary-icons", "--icons=never"]);
+    assert_eq!(plain.lines().last(), Some("1 directory, 3 files, 2B total"), "{}", plain);
+}
+
+#[test]
+fn max_entries_per_dir_caps_each_tree_level_but_not_the_report() {
+    let tmp = Scratch::new("max-entries");
+    for i in 1..=100 {
+        tmp.file(&format!("big/f{:03}", i), "");
+    }
+    let tree = stdout(tmp.path(), &["--tree=0", "--ascii", "--icons=never", "--max-entries-per-dir=5", "--report"]);
+    let expected = "`--   big\n    |--   f001\n    |--   f002\n    |--   f003\n    |--   f004\n    |--   f005\n    `-- ... (95 more)\n\n1 directory, 100 files, 0B total\n";
+    assert_eq!(tree, expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_names_renders_each_line_as_an_entry() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = Scratch::new("stdin-names");
+    fs::create_dir(tmp.path().join("d")).unwrap();
+    let tool = tmp.file("tool.sh", "");
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    tmp.file("notes.txt", "");
+    let mut child = rdir(tmp.path())
+        .args(["--stdin-names", "--color=always", "--icon-set=ascii"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"d\ntool.sh\nnotes.txt\n").unwrap();
+    let out = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3, "{:?}", out);
+    assert_eq!(lines[0], "  \x1b[34m[d]\x1b[0m \x1b[34md\x1b[0m");
+    assert!(lines[1].ends_with(" \x1b[32mtool.sh\x1b[0m"), "{:?}", lines[1]);
+    assert!(lines[2].starts_with("  \x1b[37m[txt]\x1b[0m "), "{:?}", lines[2]);
+}
+
+#[test]
+fn si_sizes_stay_aligned_in_long_listings() {
+    let tmp = Scratch::new("si");
+    tmp.file("big", &"x".repeat(1_500_000));
+    tmp.file("small", "x");
+    let long = stdout(tmp.path(), &["-l", "--si", "--icons=never"]);
+    let rows: Vec<&str> = long.lines().skip(1).collect();
+    let big = rows[0].find("1.5MB").unwrap();
+    assert_eq!(rows[1].find("1B").unwrap() + 2, big + 5, "{}", long);
+}
+
+#[test]
+fn uniform_size_unit_gives_every_size_one_suffix() {
+    let tmp = Scratch::new("uniform-size");
+    tmp.file("one", "x");
+    tmp.file("mid", &"x".repeat(5000));
+    tmp.file("big", &"x".repeat(3_000_000));
+    let listing = stdout(tmp.path(), &["-l", "--uniform-size-unit", "--icons=never"]);
+    let sizes: Vec<&str> = listing.lines().skip(1).map(|line| line.split_whitespace().nth(4).unwrap()).collect();
+    assert_eq!(sizes, ["2.9M", "0.005M", "0.000001M"], "{}", listing);
+}
+
+#[cfg(unix)]
+#[test]
+fn owner_and_group_filters_keep_matching_entries() {
+    use std::os::unix::fs::MetadataExt;
+    let tmp = Scratch::new("owner");
+    let mine = tmp.file("mine", "");
+    let other = tmp.file("other", "");
+    let md = fs::metadata(&mine).unwrap();
+    let (uid, gid) = (md.uid().to_string(), md.gid().to_string());
+    let name = Command::new("id").arg("-un").output().unwrap().stdout;
+    let name = String::from_utf8(name).unwrap().trim().to_string();
+    // Only root can hand a file to someone else.
+    let chowned = std::os::unix::fs::chown(&other, Some(65534), Some(65534)).is_ok();
+    let own = if chowned { vec!["mine"] } else { vec!["mine", "other"] };
+    assert_eq!(listed(tmp.path(), &[&format!("--owner={}", uid)]), own);
+    assert_eq!(listed(tmp.path(), &[&format!("--owner={}", name)]), own);
+    assert_eq!(listed(tmp.path(), &[&format!("--group={}", gid)]), own);
+    if chowned {
+        assert_eq!(listed(tmp.path(), &["--owner=65534"]), ["other"]);
+        assert_eq!(listed(tmp.path(), &["--group=65534"]), ["other"]);
+    }
+}
+
+#[test]
+fn edge_spaces_in_names_are_marked() {
+    let tmp = Scratch::new("mark-spaces");
+    tmp.file(" spaced ", "");
+    tmp.file("in side", "");
+    assert_eq!(stdout(tmp.path(), &["-1", "--icons=never"]), "  \u{2423}spaced\u{2423}\n  in side\n");
+    assert_eq!(stdout(tmp.path(), &["-1", "--icons=never", "--no-mark-spaces"]), "   spaced \n  in side\n");
+}
+
+#[test]
+fn icon_set_nerd_swaps_emoji_for_nerd_font_glyphs() {
+    let tmp = Scratch::new("icon-set-nerd");
+    tmp.file("main.rs", "");
+    let emoji = stdout(tmp.path(), &["-1", "--icons=always"]);
+    let nerd = stdout(tmp.path(), &["-1", "--icons=always", "--icon-set=nerd"]);
+    assert!(nerd.contains("\u{e7a8} main.rs"), "{:?}", nerd);
+    assert!(!emoji.contains('\u{e7a8}'), "{:?}", emoji);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn entries_on_one_filesystem_share_a_device() {
+    use std::os::unix::fs::MetadataExt;
+    let tmp = Scratch::new("show-device");
+    tmp.file("a", "");
+    tmp.file("sub/b", "");
+    let dev = fs::metadata(tmp.path()).unwrap().dev().to_string();
+    let column = |args: &[&str]| -> Vec<String> {
+        let long = stdout(tmp.path(), &[&["-l", "--icons=never"], args].concat());
+        long.lines().skip(1).map(|row| row.split_whitespace().nth(4).unwrap().to_string()).collect()
+    };
+    assert_eq!(column(&["--show-device"]), [dev.clone(), dev]);
+    let mounts = column(&["--resolve-mounts"]);
+    assert!(mounts[0].starts_with('/') && mounts[0] == mounts[1], "{:?}", mounts);
+}
+
+#[test]
+fn trees_group_files_or_dirs_first_like_flat_listings() {
+    let tmp = Scratch::new("tree-groups");
+    for name in ["a", "c", "b/x", "b/z", "b/y/keep", "d/keep"] {
+        tmp.file(name, "");
+    }
+    let tree = |flag: &str| stdout(tmp.path(), &["--tree=2", "--ascii", "--icons=never", flag]);
+    // Without a grouping flag each level is one sequence by name.
+    assert_eq!(tree("--sort=name"), "|--   a\n|--   b\n|   |--   x\n|   |--   y\n|   `--   z\n|--   c\n`--   d\n    `--   keep\n");
+    assert_eq!(tree("--sf"), "|--   a\n|--   c\n|--   b\n|   |--   x\n|   |--   z\n|   `--   y\n`--   d\n    `--   keep\n");
+    assert_eq!(tree("--sd"), "|--   b\n|   |--   y\n|   |--   x\n|   `--   z\n|--   d\n|   `--   keep\n|--   a\n`--   c\n");
+    assert_eq!(listed(tmp.path(), &["--sf"]), ["a", "c", "b", "d"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_lists_each_directory_under_its_header() {
+    let tmp = Scratch::new("recursive");
+    for name in ["rr/top", "rr/.hidden", "rr/a/x", "rr/b/c/y"] {
+        tmp.file(name, "");
+    }
+    std::os::unix::fs::symlink("../b", tmp.path().join("rr/a/link")).unwrap();
+    let listing = stdout(tmp.path(), &["-R", "-1", "--icons=never", "rr"]);
+    assert_eq!(listing, "rr:\n  a\n  b\n  top\n\nrr/a:\n  link -> ../b\n  x\n\nrr/b:\n  c\n\nrr/b/c:\n  y\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn dereferenced_trees_stop_at_a_link_back_to_an_ancestor() {
+    let tmp = Scratch::new("tree-cycle");
+    tmp.file("cy/sub/f", "");
+    std::os::unix::fs::symlink("..", tmp.path().join("cy/sub/up")).unwrap();
+    let tree = stdout(tmp.path(), &["--tree=0", "--ascii", "--icons=never", "-L", "cy"]);
+    assert_eq!(tree, "`--   sub\n    |--   f\n    `--   up [recursion detected]\n");
+}
+
+#[test]
+fn width_overrides_columns_and_rejects_junk() {
+    let tmp = Scratch::new("width");
+    for name in ["aaaa", "bbbb", "cccc", "dddd"] {
+        tmp.file(name, "");
+    }
+    let grid = |width: &str| {
+        let out = rdir(tmp.path()).env("COLUMNS", "200").args(["--icons=never", width]).output().unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+    assert_eq!(grid("--width=80"), "  aaaa    bbbb    cccc    dddd\n");
+    assert_eq!(grid("--width=20"), "  aaaa    cccc\n  bbbb    dddd\n");
+    assert_eq!(grid("--width=0"), "  aaaa\n  bbbb\n  cccc\n  dddd\n");
+    for bad in ["--width=x", "--width=-1"] {
+        let out = rdir(tmp.path()).arg(bad).output().unwrap();
+        assert_eq!(out.status.code(), Some(1), "{}", bad);
+        assert!(String::from_utf8(out.stderr).unwrap().starts_with("Invalid value for --width"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn long_directory_listings_start_with_a_block_total() {
+    use std::os::unix::fs::MetadataExt;
+    let tmp = Scratch::new("total");
+    let a = tmp.file("d/a", &"x".repeat(5000));
+    let b = tmp.file("d/b", "");
+    let total = |block: u64| -> u64 { [&a, &b].iter().map(|p| (fs::metadata(p).unwrap().blocks() * 512).div_ceil(block)).sum() };
+    let long = stdout(tmp.path(), &["-l", "--icons=never", "d"]);
+    assert_eq!(long.lines().next(), Some(format!("total {}", total(1024)).as_str()));
+    let posix = stdout(tmp.path(), &["-l", "--icons=never", "--block-size=512", "d"]);
+    assert_eq!(posix.lines().next(), Some(format!("total {}", total(512)).as_str()));
+    let named = stdout(tmp.path(), &["-l", "--icons=never", "d/a"]);
+    assert!(!named.contains("total"), "{}", named);
+}
+
+#[test]
+fn named_files_are_listed_before_directories() {
+    let tmp = Scratch::new("named-args");
+    tmp.file("b", "");
+    tmp.file("a", "");
+    tmp.file("sub/c", "");
+    let out = rdir(tmp.path()).args(["-1", "--icons=never", "b", "sub", "a"]).output().unwrap();
+    assert!(out.status.success() && out.stderr.is_empty(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "  a\n  b\n\nsub:\n  c\n");
+}
+
+#[test]
+fn progress_on_stderr_leaves_stdout_alone() {
+    let tmp = Scratch::new("progress");
+    for name in ["a/one", "a/b/two", "c/three", "four"] {
+        tmp.file(name, "");
+    }
+    let plain = stdout(tmp.path(), &["-R", "--total-size", "-l"]);
+    let out = rdir(tmp.path()).args(["--progress=always", "-R", "--total-size", "-l"]).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), plain);
+    assert_eq!(stdout(tmp.path(), &["--progress=never", "-R", "--total-size", "-l"]), plain);
+
+    let out = rdir(tmp.path()).arg("--progress=sometimes").output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid value for --progress: sometimes"));
+}
+
+#[test]
+fn depth_colors_tint_each_tree_level_differently() {
+    let tmp = Scratch::new("depth-colors");
+    tmp.file("a/b/c", "");
+    let tree = stdout(tmp.path(), &["--tree", "--depth-colors", "--color=always", "--icons=never", "--ascii"]);
+    let connector_colour = |line: &str| line[..line.find('m').unwrap() + 1].to_string();
+    let lines: Vec<&str> = tree.lines().collect();
+    assert_eq!(lines.len(), 3, "{:?}", tree);
+    assert!(lines[0].starts_with("\x1b[") && lines[1].starts_with("\x1b["), "{:?}", tree);
+    assert_ne!(connector_colour(lines[0]), connector_colour(lines[1]), "{:?}", tree);
+    assert_ne!(connector_colour(lines[1]), connector_colour(lines[2]), "{:?}", tree);
+
+    let plain = stdout(tmp.path(), &["--tree", "--color=always", "--icons=never", "--ascii"]);
+    assert!(plain.starts_with("`-- "), "{:?}", plain);
+}